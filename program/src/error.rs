@@ -35,6 +35,28 @@ pub enum AppError {
   InvalidMint,
   #[error("Exceed limit")]
   ExceedLimit,
+  #[error("Dust amount")]
+  DustAmount,
+  #[error("Unauthorized")]
+  Unauthorized,
+  #[error("Invariant violation")]
+  InvariantViolation,
+  #[error("Account frozen")]
+  Frozen,
+  #[error("Delegate still active")]
+  DelegateStillActive,
+  #[error("Quote expired")]
+  Expired,
+  #[error("Excessive swap amount")]
+  ExcessiveSwap,
+  #[error("Ask reserve below the minimum")]
+  BelowMinReserve,
+  #[error("Pool reserve full")]
+  PoolFull,
+  #[error("LPT supply full")]
+  LptSupplyFull,
+  #[error("Same LPT account")]
+  SamedLpt,
 }
 
 impl From<AppError> for ProgramError {
@@ -66,6 +88,17 @@ impl PrintProgramError for AppError {
       AppError::InsufficientFunds => msg!("Error: Insufficient funds"),
       AppError::InvalidMint => msg!("Error: Invalid mint"),
       AppError::ExceedLimit => msg!("Error: Exceed limit"),
+      AppError::DustAmount => msg!("Error: Dust amount"),
+      AppError::Unauthorized => msg!("Error: Unauthorized"),
+      AppError::InvariantViolation => msg!("Error: Invariant violation"),
+      AppError::Frozen => msg!("Error: Account frozen"),
+      AppError::DelegateStillActive => msg!("Error: Delegate still active"),
+      AppError::Expired => msg!("Error: Quote expired"),
+      AppError::ExcessiveSwap => msg!("Error: Excessive swap amount"),
+      AppError::BelowMinReserve => msg!("Error: Ask reserve below the minimum"),
+      AppError::PoolFull => msg!("Error: Pool reserve full"),
+      AppError::LptSupplyFull => msg!("Error: LPT supply full"),
+      AppError::SamedLpt => msg!("Error: Same LPT account"),
     }
   }
 }
@@ -0,0 +1,35 @@
+use crate::error::AppError;
+use crate::schema::pool::Pool;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+///
+/// The signer must be the current owner of the pool
+/// An unsigned caller is Unauthorized, a signer other than the owner is InvalidOwner
+///
+pub fn require_pool_owner(signer: &AccountInfo, pool_data: &Pool) -> ProgramResult {
+  if !signer.is_signer {
+    return Err(AppError::Unauthorized.into());
+  }
+  if pool_data.owner != *signer.key {
+    return Err(AppError::InvalidOwner.into());
+  }
+  Ok(())
+}
+
+///
+/// The signer must be the pool owner or, when set, the treasury authority
+///
+pub fn require_treasury_authority(signer: &AccountInfo, pool_data: &Pool) -> ProgramResult {
+  if !signer.is_signer {
+    return Err(AppError::Unauthorized.into());
+  }
+  if pool_data.owner == *signer.key {
+    return Ok(());
+  }
+  if pool_data.treasury_authority != Pubkey::default()
+    && pool_data.treasury_authority == *signer.key
+  {
+    return Ok(());
+  }
+  Err(AppError::InvalidOwner.into())
+}
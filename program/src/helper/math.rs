@@ -47,3 +47,142 @@ impl Roots for u128 {
     end
   }
 }
+
+///
+/// Implement multiply-then-divide for u128 with a 256-bit intermediate
+///
+pub trait MulDiv {
+  fn mul_div(self, b: Self, denom: Self) -> Option<Self>
+  where
+    Self: Sized;
+  fn mul_div_ceil(self, b: Self, denom: Self) -> Option<Self>
+  where
+    Self: Sized;
+}
+
+impl MulDiv for u128 {
+  ///
+  /// Floor of self * b / denom, None if denom is zero or the quotient overflows
+  ///
+  fn mul_div(self, b: Self, denom: Self) -> Option<Self> {
+    let (quo, _) = mul_div_rem(self, b, denom)?;
+    Some(quo)
+  }
+
+  ///
+  /// Ceiling of self * b / denom, None if denom is zero or the quotient overflows
+  ///
+  fn mul_div_ceil(self, b: Self, denom: Self) -> Option<Self> {
+    let (quo, rem) = mul_div_rem(self, b, denom)?;
+    if rem == 0 {
+      return Some(quo);
+    }
+    quo.checked_add(1)
+  }
+}
+
+///
+/// Quotient and remainder of a * b / denom
+///
+fn mul_div_rem(a: u128, b: u128, denom: u128) -> Option<(u128, u128)> {
+  if denom == 0 {
+    return None;
+  }
+  if let Some(product) = a.checked_mul(b) {
+    return Some((product / denom, product % denom));
+  }
+
+  let (hi, lo) = full_mul(a, b);
+  if hi >= denom {
+    return None;
+  }
+  // Restoring long division of (hi, lo) by denom
+  let mut rem = hi;
+  let mut quo: u128 = 0;
+  for i in (0..128).rev() {
+    let carry = rem >> 127;
+    rem = (rem << 1) | ((lo >> i) & 1);
+    quo <<= 1;
+    if carry == 1 || rem >= denom {
+      rem = rem.wrapping_sub(denom);
+      quo |= 1;
+    }
+  }
+  Some((quo, rem))
+}
+
+///
+/// 128 x 128 -> 256 bits multiplication as (hi, lo)
+///
+fn full_mul(a: u128, b: u128) -> (u128, u128) {
+  let mask = u64::MAX as u128;
+  let (a_hi, a_lo) = (a >> 64, a & mask);
+  let (b_hi, b_lo) = (b >> 64, b & mask);
+  let lo_lo = a_lo * b_lo;
+  let hi_lo = a_hi * b_lo;
+  let lo_hi = a_lo * b_hi;
+  let hi_hi = a_hi * b_hi;
+  let mid = (lo_lo >> 64) + (hi_lo & mask) + (lo_hi & mask);
+  let lo = (lo_lo & mask) | (mid << 64);
+  let hi = hi_hi + (hi_lo >> 64) + (lo_hi >> 64) + (mid >> 64);
+  (hi, lo)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn mul_div_handles_products_beyond_u128() {
+    let max = u128::MAX;
+    assert!(max.checked_mul(max).is_none());
+    assert_eq!(max.mul_div(max, max), Some(max));
+    assert_eq!((1u128 << 100).mul_div(3 << 90, 1 << 95), Some(3 << 95));
+    // The wide path agrees with the narrow one on scaled inputs
+    for &(a, b, denom) in [
+      (7u128, 11u128, 3u128),
+      (1 << 60, 12345, 999),
+      (max >> 64, 3, 7),
+    ]
+    .iter()
+    {
+      let scaled = (a << 64).mul_div(b, denom << 64);
+      assert_eq!(scaled, a.mul_div(b, denom));
+      assert_eq!(scaled, Some(a * b / denom));
+    }
+  }
+
+  #[test]
+  fn mul_div_rejects_a_zero_denominator() {
+    assert_eq!(5u128.mul_div(7, 0), None);
+    assert_eq!(u128::MAX.mul_div(u128::MAX, 0), None);
+    assert_eq!(5u128.mul_div_ceil(7, 0), None);
+  }
+
+  #[test]
+  fn mul_div_by_one_is_the_product() {
+    assert_eq!(5u128.mul_div(7, 1), Some(35));
+    assert_eq!(u128::MAX.mul_div(1, 1), Some(u128::MAX));
+    // The product itself no longer fits
+    assert_eq!(u128::MAX.mul_div(2, 1), None);
+  }
+
+  #[test]
+  fn mul_div_rejects_quotients_beyond_u128() {
+    assert_eq!(u128::MAX.mul_div(3, 2), None);
+    assert_eq!(u128::MAX.mul_div(u128::MAX, u128::MAX - 1), None);
+  }
+
+  #[test]
+  fn mul_div_ceil_rounds_only_a_remainder_up() {
+    assert_eq!(7u128.mul_div(3, 2), Some(10));
+    assert_eq!(7u128.mul_div_ceil(3, 2), Some(11));
+    assert_eq!(8u128.mul_div_ceil(3, 2), Some(12));
+    let max = u128::MAX;
+    assert_eq!(max.mul_div_ceil(max - 1, max), Some(max - 1));
+    // 7 * b = 2 * max + 1, the floor fits but rounding up does not
+    let b = 97223533405982418132392744980505203273u128;
+    assert_eq!(7u128.mul_div(b, 2), Some(max));
+    assert_eq!(7u128.mul_div_ceil(b, 2), None);
+  }
+}
@@ -1,3 +1,5 @@
+pub mod acl;
 pub mod math;
 pub mod oracle;
 pub mod pubutil;
+pub mod validate;
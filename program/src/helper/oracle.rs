@@ -1,51 +1,206 @@
-use crate::helper::math::Roots;
+use crate::error::AppError;
+use crate::helper::math::{MulDiv, Roots};
+use std::convert::TryFrom;
 
 const TRIPPLE_PRECISION: u128 = 1000000000000000000; // 10^18
-const FEE: u64 = 2500000; // 0.25%
-const EARNING: u64 = 500000; // 0.05%
-const DECIMALS: u64 = 1000000000; // 10^9
+pub const FEE: u64 = 2500000; // 0.25%
+pub const EARNING: u64 = 500000; // 0.05%
+pub const DECIMALS: u64 = 1000000000; // 10^9
+
+// A swap that empties most of the ask reserve leaves an earning that is large next to what
+// remains, and buying it back could take a real share of S, so 90% of S must survive it
+pub const SEN_FLOOR: u64 = 900000000;
+
+///
+/// Fixed-point rate over DECIMALS, e.g. Rate(FEE) = 2500000 / 10^9 = 25 bps
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rate(pub u64);
+
+impl Rate {
+  // Rate of the amount, rounded up so the pool keeps the rounding
+  pub fn apply(&self, amount: u64) -> Option<u64> {
+    let value = (amount as u128).mul_div_ceil(self.0 as u128, DECIMALS as u128)?;
+    u64::try_from(value).ok()
+  }
+  // Rate in basis points, rounded down, for display
+  pub fn to_bps(&self) -> u64 {
+    (self.0 as u128 * 10000 / DECIMALS as u128) as u64
+  }
+}
+
+///
+/// Reserves (indexed S, A, B) and transfers of a settled swap
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SwapResult {
+  pub reserves: [u64; 3],
+  pub paid_amount: u64,
+  pub earning_in_sen: u64,
+}
 
 pub struct Oracle {}
 
 impl Oracle {
+  // (fee, earning, decimals) as applied by curve_in_fee
+  pub fn fee_params() -> (u64, u64, u64) {
+    (FEE, EARNING, DECIMALS)
+  }
+
   pub fn curve(new_bid_reserve: u64, bid_reserve: u64, ask_reserve: u64) -> Option<u64> {
     if new_bid_reserve == 0 || bid_reserve == 0 || ask_reserve == 0 {
       return None;
     }
-    let new_ask_reserve = (bid_reserve as u128)
-      .checked_mul(ask_reserve as u128)?
-      .checked_div(new_bid_reserve as u128)? as u64;
+    // Round up so the pool keeps the rounding
+    let new_ask_reserve =
+      (bid_reserve as u128).mul_div_ceil(ask_reserve as u128, new_bid_reserve as u128)? as u64;
     if new_ask_reserve == 0 {
       return None;
     }
+    Self::check_curve(new_bid_reserve, bid_reserve, ask_reserve, new_ask_reserve);
 
     Some(new_ask_reserve)
   }
 
+  // Debug-only cross-check against the invariant: k never shrinks and rounding adds under one unit
+  fn check_curve(new_bid_reserve: u64, bid_reserve: u64, ask_reserve: u64, new_ask_reserve: u64) {
+    let k = (bid_reserve as u128) * (ask_reserve as u128);
+    debug_assert!((new_bid_reserve as u128) * (new_ask_reserve as u128) >= k);
+    debug_assert!((new_bid_reserve as u128) * (new_ask_reserve.saturating_sub(1) as u128) < k);
+  }
+
   pub fn curve_in_fee(
     new_bid_reserve: u64,
     bid_reserve: u64,
     ask_reserve: u64,
     is_exempted: bool,
-  ) -> Option<(u64, u64, u64)> {
-    let new_ask_reserve_without_fee = Self::curve(new_bid_reserve, bid_reserve, ask_reserve)?;
-    let paid_amount_without_fee = ask_reserve.checked_sub(new_ask_reserve_without_fee)?;
+  ) -> Result<(u64, u64, u64), AppError> {
+    let new_ask_reserve_without_fee =
+      Self::curve(new_bid_reserve, bid_reserve, ask_reserve).ok_or(AppError::Overflow)?;
+    // A bid can never grow the ask reserve
+    if new_ask_reserve_without_fee > ask_reserve {
+      return Err(AppError::InvariantViolation);
+    }
+    let paid_amount_without_fee = ask_reserve
+      .checked_sub(new_ask_reserve_without_fee)
+      .ok_or(AppError::Overflow)?;
 
-    let fee = (paid_amount_without_fee as u128)
-      .checked_mul(FEE as u128)?
-      .checked_div(DECIMALS as u128)? as u64;
+    // Round fee and earning up, the paid amount takes the rounding
+    let fee = Rate(FEE)
+      .apply(paid_amount_without_fee)
+      .ok_or(AppError::Overflow)?;
     let mut earning: u64 = 0;
     if !is_exempted {
-      earning = (paid_amount_without_fee as u128)
-        .checked_mul(EARNING as u128)?
-        .checked_div(DECIMALS as u128)? as u64;
+      earning = Rate(EARNING)
+        .apply(paid_amount_without_fee)
+        .ok_or(AppError::Overflow)?;
     }
 
+    // The swap is too small to cover its own fee and earning
     let paid_amount = paid_amount_without_fee
-      .checked_sub(fee)?
-      .checked_sub(earning)?;
-    let new_ask_reserve = new_ask_reserve_without_fee.checked_add(fee)?;
-    Some((new_ask_reserve, paid_amount, earning))
+      .checked_sub(fee)
+      .and_then(|amount| amount.checked_sub(earning))
+      .ok_or(AppError::DustAmount)?;
+    let new_ask_reserve = new_ask_reserve_without_fee
+      .checked_add(fee)
+      .ok_or(AppError::Overflow)?;
+    Ok((new_ask_reserve, paid_amount, earning))
+  }
+
+  // Whether curve_in_fee, charging these rates, settles a non-zero earning on this output
+  // before fees. A dust output that cannot cover both is rejected there, so it never earns
+  pub fn will_earn(
+    is_exempted: bool,
+    fee: Rate,
+    earning: Rate,
+    paid_amount_without_fee: u64,
+  ) -> bool {
+    if is_exempted {
+      return false;
+    }
+    match (
+      fee.apply(paid_amount_without_fee),
+      earning.apply(paid_amount_without_fee),
+    ) {
+      (Some(fee), Some(earning)) => {
+        earning > 0
+          && matches!(fee.checked_add(earning), Some(charge) if charge <= paid_amount_without_fee)
+      }
+      _ => false,
+    }
+  }
+
+  // (expected, min) output of a swap, min is the limit to pass to Swap
+  pub fn quote_with_slippage(
+    amount: u64,
+    bid_reserve: u64,
+    ask_reserve: u64,
+    is_exempted: bool,
+    slippage_bps: u64,
+  ) -> Option<(u64, u64)> {
+    if slippage_bps > 10000 {
+      return None;
+    }
+    let new_bid_reserve = bid_reserve.checked_add(amount)?;
+    let (_, expected_out, _) =
+      Self::curve_in_fee(new_bid_reserve, bid_reserve, ask_reserve, is_exempted).ok()?;
+    // Round down so a fill exactly at the tolerance still lands
+    let min_out = (expected_out as u128).mul_div(10000 - slippage_bps as u128, 10000)? as u64;
+    Some((expected_out, min_out))
+  }
+
+  // Pure mirror of the arithmetic in Swap, for differential tests against the processor.
+  // Reserves and codes follow Pool::get_reserve, bid and ask must differ
+  pub fn reference_swap(
+    reserves: [u64; 3],
+    bid_code: usize,
+    ask_code: usize,
+    amount: u64,
+  ) -> Result<SwapResult, AppError> {
+    if amount == 0 {
+      return Err(AppError::ZeroValue);
+    }
+    let bid_reserve = reserves[bid_code];
+    let ask_reserve = reserves[ask_code];
+    let new_bid_reserve = bid_reserve
+      .checked_add(amount)
+      .ok_or(AppError::ExcessiveSwap)?;
+    let (new_ask_reserve, paid_amount, earning) =
+      Self::curve_in_fee(new_bid_reserve, bid_reserve, ask_reserve, ask_code == 0)?;
+    let mut result = SwapResult {
+      reserves,
+      paid_amount,
+      earning_in_sen: 0,
+    };
+    result.reserves[bid_code] = new_bid_reserve;
+    result.reserves[ask_code] = new_ask_reserve;
+    if earning == 0 {
+      return Ok(result);
+    }
+
+    // The earning joins the ask reserve, then buys S unless that drains S below the floor
+    let new_ask_reserve_with_earning = new_ask_reserve
+      .checked_add(earning)
+      .ok_or(AppError::Overflow)?;
+    result.reserves[ask_code] = new_ask_reserve_with_earning;
+    let reserve_s = result.reserves[0];
+    if reserve_s == 0 {
+      return Err(AppError::InsufficientFunds);
+    }
+    let sen_floor = (reserve_s as u128)
+      .mul_div(SEN_FLOOR as u128, DECIMALS as u128)
+      .ok_or(AppError::Overflow)? as u64;
+    let (new_sen_reserve, earning_in_sen, _) = Self::curve_in_fee(
+      new_ask_reserve_with_earning,
+      new_ask_reserve,
+      reserve_s,
+      true,
+    )?;
+    if new_sen_reserve >= sen_floor {
+      result.reserves[0] = new_sen_reserve;
+      result.earning_in_sen = earning_in_sen;
+    }
+    Ok(result)
   }
 
   pub fn _rake(
@@ -67,9 +222,8 @@ impl Oracle {
       .cbrt(); // Single precision
     let cbrt_of_reserve = (reserve_s as u128).checked_mul(TRIPPLE_PRECISION)?.cbrt(); // Single precision
     let z = cbrt_of_delta_plus_reserve
-      .pow(2)
-      .checked_mul(cbrt_of_reserve)?
-      .checked_div(TRIPPLE_PRECISION)?
+      .checked_pow(2)?
+      .mul_div(cbrt_of_reserve, TRIPPLE_PRECISION)?
       .checked_sub(reserve_s as u128)?;
     // Compute x
     let x = z
@@ -81,12 +235,8 @@ impl Oracle {
     let y = z.checked_sub(x)?;
     // Compute s, a, b
     let s = (delta as u128).checked_sub(z)? as u64;
-    let a = (reserve_a as u128)
-      .checked_mul(x)?
-      .checked_div((reserve_s as u128).checked_add(x)?)? as u64;
-    let b = (reserve_b as u128)
-      .checked_mul(y)?
-      .checked_div((reserve_s as u128).checked_add(z)?)? as u64;
+    let a = (reserve_a as u128).mul_div(x, (reserve_s as u128).checked_add(x)?)? as u64;
+    let b = (reserve_b as u128).mul_div(y, (reserve_s as u128).checked_add(z)?)? as u64;
     // Return
     Some((s, a, b))
   }
@@ -103,32 +253,417 @@ impl Oracle {
     let rs = reserve_s;
     let ra = reserve_a;
     let rb = reserve_b;
-    let rlpt = reserve_lpt;
+    let rlpt = reserve_lpt as u128;
 
+    // The running supply is u128 so that a full supply surfaces in the caller, not here
     let (s1, _a1, _b1) = Self::_rake(delta_s, rs, ra, rb)?;
     let rs = rs.checked_add(delta_s)?;
     let rs_prime = rs.checked_sub(s1)?;
-    let lpt1 = (s1 as u128)
-      .checked_mul(rlpt as u128)?
-      .checked_div(rs_prime as u128)? as u64;
-    let rlpt = rlpt.checked_add(lpt1)?;
+    let lpt1 = u64::try_from((s1 as u128).mul_div(rlpt, rs_prime as u128)?).ok()?;
+    let rlpt = rlpt + lpt1 as u128;
 
     let (_a2, _b2, s2) = Self::_rake(delta_a, ra, rb, rs)?;
     let ra = ra.checked_add(delta_a)?;
     let rs_prime = rs.checked_sub(s2)?;
-    let lpt2 = (s2 as u128)
-      .checked_mul(rlpt as u128)?
-      .checked_div(rs_prime as u128)? as u64;
-    let rlpt = rlpt.checked_add(lpt2)?;
+    let lpt2 = u64::try_from((s2 as u128).mul_div(rlpt, rs_prime as u128)?).ok()?;
+    let rlpt = rlpt + lpt2 as u128;
 
     let (_b3, s3, _a3) = Self::_rake(delta_b, rb, rs, ra)?;
     let rb = rb.checked_add(delta_b)?;
     let rs_prime = rs.checked_sub(s3)?;
-    let lpt3 = (s3 as u128)
-      .checked_mul(rlpt as u128)?
-      .checked_div(rs_prime as u128)? as u64;
+    let lpt3 = u64::try_from((s3 as u128).mul_div(rlpt, rs_prime as u128)?).ok()?;
     let lpt = lpt1.checked_add(lpt2)?.checked_add(lpt3)?;
 
     Some((lpt, rs, ra, rb))
   }
+
+  // Smallest S deposit whose minted LPT meets or exceeds target_lpt
+  pub fn reserve_for_lpt(
+    target_lpt: u64,
+    reserve_s: u64,
+    reserve_a: u64,
+    reserve_b: u64,
+    reserve_lpt: u64,
+  ) -> Option<u64> {
+    if target_lpt == 0 {
+      return Some(0);
+    }
+    let mint = |delta_s| Self::rake(delta_s, 0, 0, reserve_s, reserve_a, reserve_b, reserve_lpt);
+    // Rake refuses deposits too small to mint and deposits too large to count,
+    // so double up to a deposit that reaches the target before bisecting
+    let mut high: u64 = 1;
+    let mut minted = false;
+    loop {
+      match mint(high) {
+        Some((lpt, _, _, _)) if lpt >= target_lpt => break,
+        Some(_) => minted = true,
+        None if minted => return None,
+        None => {}
+      }
+      high = high.checked_mul(2)?;
+    }
+    // Below a deposit rake accepts, a refusal means too small
+    let mut low = high / 2 + 1;
+    while low < high {
+      let mid = low + (high - low) / 2;
+      match mint(mid) {
+        Some((lpt, _, _, _)) if lpt >= target_lpt => high = mid,
+        _ => low = mid + 1,
+      }
+    }
+    Some(high)
+  }
+
+  // Split a bid across pools of the same pair so their marginal prices end equal
+  // Pools are given as (bid_reserve, ask_reserve), orders as (pool index, bid amount)
+  // None if no pool has both reserves
+  pub fn split_order(amount: u64, pools: &[(u64, u64)]) -> Option<Vec<(usize, u64)>> {
+    let mut candidates: Vec<(usize, u128, u128)> = pools
+      .iter()
+      .enumerate()
+      .filter(|(_, &(bid, ask))| bid != 0 && ask != 0)
+      .map(|(i, &(bid, ask))| (i, bid as u128, (bid as u128 * ask as u128).sqrt()))
+      .collect();
+    if candidates.is_empty() {
+      return None;
+    }
+    // Best price first
+    candidates.sort_by(|(_, bid_i, sqrt_k_i), (_, bid_j, sqrt_k_j)| {
+      (bid_i * sqrt_k_j).cmp(&(bid_j * sqrt_k_i))
+    });
+
+    // Every active pool is filled up to a bid reserve of level * sqrt(k)
+    let mut num = amount as u128;
+    let mut den: u128 = 0;
+    let mut active = 0;
+    for (_, bid, sqrt_k) in candidates.iter() {
+      if active > 0 && num.mul_div(*sqrt_k, den)? <= *bid {
+        break;
+      }
+      num = num.checked_add(*bid)?;
+      den = den.checked_add(*sqrt_k)?;
+      active += 1;
+    }
+
+    let mut orders = Vec::with_capacity(active);
+    let mut remaining = amount;
+    for (i, bid, sqrt_k) in candidates.iter().take(active) {
+      let level = num.mul_div(*sqrt_k, den)?;
+      let size = level.saturating_sub(*bid).min(remaining as u128) as u64;
+      remaining -= size;
+      orders.push((*i, size));
+    }
+    // Rounding dust goes to the best pool
+    if let Some(order) = orders.first_mut() {
+      order.1 = order.1.checked_add(remaining)?;
+    }
+    orders.retain(|&(_, size)| size > 0);
+    Some(orders)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn check_curve_accepts_the_curve_output() {
+    // A bid of 1000 doubled to 2000 halves the ask to 500
+    assert_eq!(Oracle::curve(2000, 1000, 1000), Some(500));
+    Oracle::check_curve(2000, 1000, 1000, 500);
+  }
+
+  #[test]
+  #[cfg(debug_assertions)]
+  #[should_panic]
+  fn check_curve_trips_on_an_output_that_shrinks_k() {
+    Oracle::check_curve(2000, 1000, 1000, 499);
+  }
+
+  #[test]
+  #[cfg(debug_assertions)]
+  #[should_panic]
+  fn check_curve_trips_on_an_output_rounded_up_too_far() {
+    Oracle::check_curve(2000, 1000, 1000, 501);
+  }
+
+  #[test]
+  fn curve_in_fee_rejects_outputs_below_fee_and_earning() {
+    // One unit out, charged one unit of fee and one of earning
+    assert_eq!(
+      Oracle::curve_in_fee(1002, 1000, 1000, false),
+      Err(AppError::DustAmount)
+    );
+    // Exempted from earning, the fee alone eats the output
+    assert_eq!(
+      Oracle::curve_in_fee(1002, 1000, 1000, true),
+      Ok((1000, 0, 0))
+    );
+  }
+
+  // Output of routing the orders, before fees
+  fn routed_output(pools: &[(u64, u64)], orders: &[(usize, u64)]) -> u64 {
+    orders
+      .iter()
+      .map(|&(i, size)| {
+        let (bid, ask) = pools[i];
+        ask - Oracle::curve(bid + size, bid, ask).unwrap()
+      })
+      .sum()
+  }
+
+  #[test]
+  fn split_order_beats_any_single_pool() {
+    let amount = 50_000_000;
+    for pools in [
+      vec![
+        (1_000_000_000, 2_000_000_000),
+        (1_000_000_000, 2_000_000_000),
+      ],
+      vec![(1_000_000_000, 2_000_000_000), (300_000_000, 700_000_000)],
+      vec![
+        (1_000_000_000, 2_000_000_000),
+        (0, 5_000_000_000),
+        (10_000, 10_000),
+      ],
+    ]
+    .iter()
+    {
+      let orders = Oracle::split_order(amount, pools).unwrap();
+      assert_eq!(orders.iter().map(|&(_, size)| size).sum::<u64>(), amount);
+      let split = routed_output(pools, &orders);
+      for i in 0..pools.len() {
+        if pools[i].0 != 0 {
+          assert!(split >= routed_output(pools, &[(i, amount)]));
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn split_order_needs_a_usable_pool() {
+    assert_eq!(Oracle::split_order(1_000, &[]), None);
+    assert_eq!(Oracle::split_order(1_000, &[(0, 1_000), (1_000, 0)]), None);
+  }
+
+  #[test]
+  fn curve_in_fee_never_shrinks_k_and_rounds_payouts_down() {
+    let reserves = [1u64, 2, 3, 7, 999, 1_000_003, 1 << 40, u64::MAX / 3];
+    let amounts = [1u64, 2, 5, 333, 1_000_000, 1 << 33];
+    for &bid in reserves.iter() {
+      for &ask in reserves.iter() {
+        for &amount in amounts.iter() {
+          let new_bid = match bid.checked_add(amount) {
+            Some(new_bid) => new_bid,
+            None => continue,
+          };
+          for &is_exempted in [true, false].iter() {
+            let (new_ask, paid, earning) =
+              match Oracle::curve_in_fee(new_bid, bid, ask, is_exempted) {
+                Ok(result) => result,
+                Err(AppError::DustAmount) => continue,
+                Err(error) => panic!("{:?} on {} {} {}", error, bid, ask, amount),
+              };
+            // The reserve left behind, earning included, keeps k
+            let k = bid as u128 * ask as u128;
+            assert!(new_bid as u128 * (new_ask + earning) as u128 >= k);
+            // The trader gets at most the exact output, rounded down
+            let exact = (ask as u128 * amount as u128) / new_bid as u128;
+            assert!(paid as u128 <= exact);
+            assert_eq!(new_ask + paid + earning, ask);
+          }
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn rake_leaves_a_full_lpt_supply_to_the_caller() {
+    let reserve = 1_000_000_000_000;
+    let (lpt, _, _, _) = Oracle::rake(
+      1_000,
+      1_000,
+      1_000,
+      reserve,
+      reserve,
+      reserve,
+      u64::MAX - 10,
+    )
+    .unwrap();
+    assert!((u64::MAX - 10).checked_add(lpt).is_none());
+  }
+
+  #[test]
+  fn curve_in_fee_charges_earning_only_on_non_primary_asks() {
+    // 1M into 1M/1M pays 500k before fees: 1250 fee (0.25%), 250 earning (0.05%)
+    let primary = Oracle::curve_in_fee(2_000_000, 1_000_000, 1_000_000, true);
+    assert_eq!(primary, Ok((501_250, 498_750, 0)));
+    let non_primary = Oracle::curve_in_fee(2_000_000, 1_000_000, 1_000_000, false);
+    assert_eq!(non_primary, Ok((501_250, 498_500, 250)));
+  }
+
+  #[test]
+  fn will_earn_only_on_settled_non_primary_asks() {
+    let (fee, earning) = (Rate(FEE), Rate(EARNING));
+    assert!(!Oracle::will_earn(true, fee, earning, 500_000));
+    // One unit cannot cover one unit of fee and one of earning
+    assert!(!Oracle::will_earn(false, fee, earning, 1));
+    assert!(!Oracle::will_earn(false, fee, earning, 0));
+    assert!(Oracle::will_earn(false, fee, earning, 2));
+    assert!(Oracle::will_earn(false, fee, earning, 500_000));
+  }
+
+  #[test]
+  fn will_earn_agrees_with_curve_in_fee() {
+    let ask = 1_000_000;
+    for &is_exempted in [true, false].iter() {
+      for &bid in [1u64, 7, 1_000, 1_000_000, 1_000_000_000_000].iter() {
+        for &delta in [1u64, 2, 3, 999, 1_000, 123_456, 1_000_000_000_000].iter() {
+          let paid_amount_without_fee = ask - Oracle::curve(bid + delta, bid, ask).unwrap();
+          let earns = matches!(
+            Oracle::curve_in_fee(bid + delta, bid, ask, is_exempted),
+            Ok((_, _, earning)) if earning > 0
+          );
+          assert_eq!(
+            Oracle::will_earn(
+              is_exempted,
+              Rate(FEE),
+              Rate(EARNING),
+              paid_amount_without_fee
+            ),
+            earns,
+            "bid {} delta {}",
+            bid,
+            delta
+          );
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn fee_and_earning_round_up_to_the_next_unit() {
+    let (fee, earning) = (Rate(FEE), Rate(EARNING));
+    assert_eq!(fee.apply(0), Some(0));
+    assert_eq!(fee.apply(1), Some(1));
+    assert_eq!(fee.apply(400), Some(1));
+    assert_eq!(fee.apply(401), Some(2));
+    assert_eq!(earning.apply(2_000), Some(1));
+    assert_eq!(earning.apply(2_001), Some(2));
+    // Only an output of zero goes fee-free
+    let (bid, ask) = (1_000_000_000, 10);
+    assert_eq!(
+      Oracle::curve_in_fee(bid + 1, bid, ask, false),
+      Ok((10, 0, 0))
+    );
+    assert_eq!(
+      Oracle::curve_in_fee(bid + 1, bid, ask, true),
+      Ok((10, 0, 0))
+    );
+  }
+
+  #[test]
+  fn reserve_for_lpt_is_the_smallest_deposit_reaching_the_target() {
+    for &(rs, ra, rb, rlpt) in [
+      (1_000_000u64, 1_000_000u64, 1_000_000u64, 1_000_000u64),
+      (1_000_000, 3_000_000, 7_000, 250_000),
+      (999_983, 1_000_003, 1 << 40, 1 << 33),
+      (1 << 40, 1 << 20, 1 << 30, 1_000_000_000_000),
+    ]
+    .iter()
+    {
+      for &target in [1u64, 7, 1_000, 123_456].iter() {
+        let delta_s = Oracle::reserve_for_lpt(target, rs, ra, rb, rlpt).unwrap();
+        let (lpt, _, _, _) = Oracle::rake(delta_s, 0, 0, rs, ra, rb, rlpt).unwrap();
+        assert!(lpt >= target);
+        // One less either mints short or is too small for rake to accept
+        let short = Oracle::rake(delta_s - 1, 0, 0, rs, ra, rb, rlpt);
+        assert!(!matches!(short, Some((lpt, _, _, _)) if lpt >= target));
+      }
+    }
+  }
+
+  #[test]
+  fn reserve_for_lpt_needs_a_supply_to_mint_against() {
+    let reserve = 1_000_000;
+    // Nothing is needed for nothing, even without a supply
+    assert_eq!(
+      Oracle::reserve_for_lpt(0, reserve, reserve, reserve, 0),
+      Some(0)
+    );
+    // An empty supply mints nothing, so no deposit reaches a target
+    assert_eq!(
+      Oracle::reserve_for_lpt(1, reserve, reserve, reserve, 0),
+      None
+    );
+  }
+
+  #[test]
+  fn reserve_for_lpt_gives_up_on_unreachable_targets() {
+    let reserve = 1_000_000;
+    assert_eq!(
+      Oracle::reserve_for_lpt(u64::MAX, reserve, reserve, reserve, reserve),
+      None
+    );
+    // A full S reserve leaves no room for any deposit
+    assert_eq!(
+      Oracle::reserve_for_lpt(1, u64::MAX, reserve, reserve, reserve),
+      None
+    );
+  }
+
+  #[test]
+  fn quote_with_slippage_spans_no_tolerance_to_any_fill() {
+    // 1M into 1M/1M pays 498_750 after fees, as in curve_in_fee
+    let quote = |bps| Oracle::quote_with_slippage(1_000_000, 1_000_000, 1_000_000, true, bps);
+    assert_eq!(quote(0), Some((498_750, 498_750)));
+    assert_eq!(quote(10000), Some((498_750, 0)));
+    assert_eq!(quote(10001), None);
+  }
+
+  #[test]
+  fn quote_with_slippage_rounds_the_limit_down() {
+    let quote = |bps| Oracle::quote_with_slippage(1_000_000, 1_000_000, 1_000_000, true, bps);
+    // 498_750 * 0.9967 = 497_104.125
+    assert_eq!(quote(33), Some((498_750, 497_104)));
+    // On a small output even one basis point costs a whole unit
+    let small = Oracle::quote_with_slippage(10, 1_000, 1_000, true, 1);
+    assert_eq!(small, Some((8, 7)));
+  }
+
+  #[test]
+  fn to_bps_reads_stored_rates_in_basis_points() {
+    assert_eq!(Rate(FEE).to_bps(), 25);
+    assert_eq!(Rate(EARNING).to_bps(), 5);
+    assert_eq!(Rate(DECIMALS).to_bps(), 10000);
+    assert_eq!(Rate(0).to_bps(), 0);
+    // One basis point is 100000 over DECIMALS, anything short of it rounds down
+    assert_eq!(Rate(99_999).to_bps(), 0);
+    assert_eq!(Rate(100_000).to_bps(), 1);
+    assert_eq!(Rate(u64::MAX).to_bps(), u64::MAX / 100_000);
+  }
+
+  #[test]
+  fn fee_params_are_the_rates_curve_in_fee_charges() {
+    let (fee, earning, decimals) = Oracle::fee_params();
+    assert_eq!(
+      (fee, earning, decimals),
+      (2_500_000, 500_000, 1_000_000_000)
+    );
+    assert_eq!((Rate(fee).to_bps(), Rate(earning).to_bps()), (25, 5));
+    // 1M into 1M/1M pays 500k before fees, charged at exactly these rates
+    let gross = 500_000;
+    let (fee_paid, earning_paid) = (
+      Rate(fee).apply(gross).unwrap(),
+      Rate(earning).apply(gross).unwrap(),
+    );
+    assert_eq!(
+      Oracle::curve_in_fee(2_000_000, 1_000_000, 1_000_000, false),
+      Ok((
+        1_000_000 - gross + fee_paid,
+        gross - fee_paid - earning_paid,
+        earning_paid
+      ))
+    );
+  }
 }
@@ -0,0 +1,118 @@
+use crate::error::AppError;
+use crate::helper::pubutil::Boolean;
+use solana_program::pubkey::Pubkey;
+
+///
+/// Key relationships enforced by InitializePool, usable off-chain as well
+/// Every mismatch is reported, in check order, so clients can fix them at once
+///
+pub fn validate_pool_accounts(
+  program_id: &Pubkey,
+  pool: &Pubkey,
+  treasurer: &Pubkey,
+  proof: &Pubkey,
+  mint_s: &Pubkey,
+  mint_a: &Pubkey,
+  mint_b: &Pubkey,
+) -> Result<(), Vec<AppError>> {
+  let mut errors = vec![];
+  // The proof binds the mint LPT to this program and pool
+  if *proof != program_id.xor(&(pool.xor(treasurer))) {
+    errors.push(AppError::InvalidMint);
+  }
+  // A and B must differ from S
+  if *mint_s == *mint_a {
+    errors.push(AppError::InvalidMint);
+  }
+  if *mint_s == *mint_b {
+    errors.push(AppError::InvalidMint);
+  }
+  if errors.is_empty() {
+    Ok(())
+  } else {
+    Err(errors)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  struct Keys {
+    program_id: Pubkey,
+    pool: Pubkey,
+    treasurer: Pubkey,
+    proof: Pubkey,
+    mint_s: Pubkey,
+    mint_a: Pubkey,
+    mint_b: Pubkey,
+  }
+
+  impl Keys {
+    fn new() -> Self {
+      let (program_id, pool, treasurer) = (
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+      );
+      Keys {
+        program_id,
+        pool,
+        treasurer,
+        proof: program_id.xor(&(pool.xor(&treasurer))),
+        mint_s: Pubkey::new_unique(),
+        mint_a: Pubkey::new_unique(),
+        mint_b: Pubkey::new_unique(),
+      }
+    }
+
+    fn validate(&self) -> Result<(), Vec<AppError>> {
+      validate_pool_accounts(
+        &self.program_id,
+        &self.pool,
+        &self.treasurer,
+        &self.proof,
+        &self.mint_s,
+        &self.mint_a,
+        &self.mint_b,
+      )
+    }
+  }
+
+  #[test]
+  fn accepts_a_consistent_bundle() {
+    assert_eq!(Keys::new().validate(), Ok(()));
+  }
+
+  #[test]
+  fn rejects_a_proof_for_another_pool() {
+    let mut keys = Keys::new();
+    keys.proof = keys
+      .program_id
+      .xor(&(Pubkey::new_unique().xor(&keys.treasurer)));
+    assert_eq!(keys.validate(), Err(vec![AppError::InvalidMint]));
+  }
+
+  #[test]
+  fn rejects_a_mint_a_equal_to_mint_s() {
+    let mut keys = Keys::new();
+    keys.mint_a = keys.mint_s;
+    assert_eq!(keys.validate(), Err(vec![AppError::InvalidMint]));
+  }
+
+  #[test]
+  fn rejects_a_mint_b_equal_to_mint_s() {
+    let mut keys = Keys::new();
+    keys.mint_b = keys.mint_s;
+    assert_eq!(keys.validate(), Err(vec![AppError::InvalidMint]));
+  }
+
+  #[test]
+  fn reports_every_mismatch() {
+    let mut keys = Keys::new();
+    keys.proof = Pubkey::new_unique();
+    keys.mint_a = keys.mint_s;
+    keys.mint_b = keys.mint_s;
+    assert_eq!(keys.validate(), Err(vec![AppError::InvalidMint; 3]));
+  }
+}
@@ -2,6 +2,9 @@ use crate::error::AppError;
 use solana_program::program_error::ProgramError;
 use std::convert::TryInto;
 
+// Tag plus the longest payload (three u64)
+const MAX_INSTRUCTION_LEN: usize = 1 + 3 * 8;
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum AppInstruction {
   InitializePool {
@@ -20,6 +23,7 @@ pub enum AppInstruction {
   Swap {
     amount: u64,
     limit: u64,
+    slot: Option<u64>,
   },
   FreezePool,
   ThawPool,
@@ -27,29 +31,40 @@ pub enum AppInstruction {
     amount: u64,
   },
   TransferPoolOwnership,
+  TransferAndClose,
+  SetEarningMode {
+    accrue: bool,
+  },
+  SweepEarning,
+  SetMaxLptSupply {
+    max_lpt_supply: u64,
+  },
+  SwapFromSol {
+    amount: u64,
+    limit: u64,
+  },
+  ReconcileReserve,
+  SetMinReserve {
+    min_reserve_s: u64,
+    min_reserve_a: u64,
+    min_reserve_b: u64,
+  },
+  SetTreasuryAuthority,
+  SetVault,
 }
 impl AppInstruction {
   pub fn unpack(instruction: &[u8]) -> Result<Self, ProgramError> {
+    if instruction.len() > MAX_INSTRUCTION_LEN {
+      return Err(AppError::InvalidInstruction.into());
+    }
     let (&tag, rest) = instruction
       .split_first()
       .ok_or(AppError::InvalidInstruction)?;
     Ok(match tag {
       0 => {
-        let reserve_s = rest
-          .get(..8)
-          .and_then(|slice| slice.try_into().ok())
-          .map(u64::from_le_bytes)
-          .ok_or(AppError::InvalidInstruction)?;
-        let reserve_a = rest
-          .get(8..16)
-          .and_then(|slice| slice.try_into().ok())
-          .map(u64::from_le_bytes)
-          .ok_or(AppError::InvalidInstruction)?;
-        let reserve_b = rest
-          .get(16..24)
-          .and_then(|slice| slice.try_into().ok())
-          .map(u64::from_le_bytes)
-          .ok_or(AppError::InvalidInstruction)?;
+        let reserve_s = Self::read_u64(rest, 0)?;
+        let reserve_a = Self::read_u64(rest, 8)?;
+        let reserve_b = Self::read_u64(rest, 16)?;
         Self::InitializePool {
           reserve_s,
           reserve_a,
@@ -57,21 +72,9 @@ impl AppInstruction {
         }
       }
       1 => {
-        let delta_s = rest
-          .get(..8)
-          .and_then(|slice| slice.try_into().ok())
-          .map(u64::from_le_bytes)
-          .ok_or(AppError::InvalidInstruction)?;
-        let delta_a = rest
-          .get(8..16)
-          .and_then(|slice| slice.try_into().ok())
-          .map(u64::from_le_bytes)
-          .ok_or(AppError::InvalidInstruction)?;
-        let delta_b = rest
-          .get(16..24)
-          .and_then(|slice| slice.try_into().ok())
-          .map(u64::from_le_bytes)
-          .ok_or(AppError::InvalidInstruction)?;
+        let delta_s = Self::read_u64(rest, 0)?;
+        let delta_a = Self::read_u64(rest, 8)?;
+        let delta_b = Self::read_u64(rest, 16)?;
         Self::AddLiquidity {
           delta_s,
           delta_a,
@@ -79,38 +82,207 @@ impl AppInstruction {
         }
       }
       2 => {
-        let lpt = rest
-          .get(..8)
-          .and_then(|slice| slice.try_into().ok())
-          .map(u64::from_le_bytes)
-          .ok_or(AppError::InvalidInstruction)?;
+        let lpt = Self::read_u64(rest, 0)?;
         Self::RemoveLiquidity { lpt }
       }
       3 => {
-        let amount = rest
-          .get(..8)
-          .and_then(|slice| slice.try_into().ok())
-          .map(u64::from_le_bytes)
-          .ok_or(AppError::InvalidInstruction)?;
-        let limit = rest
-          .get(8..16)
-          .and_then(|slice| slice.try_into().ok())
-          .map(u64::from_le_bytes)
-          .ok_or(AppError::InvalidInstruction)?;
-        Self::Swap { amount, limit }
+        let amount = Self::read_u64(rest, 0)?;
+        let limit = Self::read_u64(rest, 8)?;
+        // Optional slot at which the quote was made, nothing else may follow
+        let slot = match rest.len() {
+          16 => None,
+          24 => Some(Self::read_u64(rest, 16)?),
+          _ => return Err(AppError::InvalidInstruction.into()),
+        };
+        Self::Swap {
+          amount,
+          limit,
+          slot,
+        }
       }
       4 => Self::FreezePool,
       5 => Self::ThawPool,
       6 => {
-        let amount = rest
-          .get(..8)
-          .and_then(|slice| slice.try_into().ok())
-          .map(u64::from_le_bytes)
-          .ok_or(AppError::InvalidInstruction)?;
+        let amount = Self::read_u64(rest, 0)?;
         Self::Earn { amount }
       }
       7 => Self::TransferPoolOwnership,
+      8 => Self::TransferAndClose,
+      9 => {
+        let accrue = match rest.first() {
+          Some(0) => false,
+          Some(1) => true,
+          _ => return Err(AppError::InvalidInstruction.into()),
+        };
+        Self::SetEarningMode { accrue }
+      }
+      10 => Self::SweepEarning,
+      11 => {
+        let max_lpt_supply = Self::read_u64(rest, 0)?;
+        Self::SetMaxLptSupply { max_lpt_supply }
+      }
+      12 => {
+        let amount = Self::read_u64(rest, 0)?;
+        let limit = Self::read_u64(rest, 8)?;
+        Self::SwapFromSol { amount, limit }
+      }
+      13 => Self::ReconcileReserve,
+      14 => {
+        let min_reserve_s = Self::read_u64(rest, 0)?;
+        let min_reserve_a = Self::read_u64(rest, 8)?;
+        let min_reserve_b = Self::read_u64(rest, 16)?;
+        Self::SetMinReserve {
+          min_reserve_s,
+          min_reserve_a,
+          min_reserve_b,
+        }
+      }
+      15 => Self::SetTreasuryAuthority,
+      16 => Self::SetVault,
       _ => return Err(AppError::InvalidInstruction.into()),
     })
   }
+
+  // Little-endian u64 at the offset, or InvalidInstruction if the data is too short
+  fn read_u64(rest: &[u8], offset: usize) -> Result<u64, AppError> {
+    rest
+      .get(offset..offset + 8)
+      .and_then(|slice| slice.try_into().ok())
+      .map(u64::from_le_bytes)
+      .ok_or(AppError::InvalidInstruction)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn unpack_reads_a_swap_with_or_without_a_slot() {
+    let mut data = vec![3];
+    data.extend_from_slice(&7u64.to_le_bytes());
+    data.extend_from_slice(&5u64.to_le_bytes());
+    assert_eq!(
+      AppInstruction::unpack(&data),
+      Ok(AppInstruction::Swap {
+        amount: 7,
+        limit: 5,
+        slot: None
+      })
+    );
+    data.extend_from_slice(&9u64.to_le_bytes());
+    assert_eq!(
+      AppInstruction::unpack(&data),
+      Ok(AppInstruction::Swap {
+        amount: 7,
+        limit: 5,
+        slot: Some(9)
+      })
+    );
+  }
+
+  #[test]
+  fn unpack_rejects_a_swap_of_any_other_length() {
+    for len in [1, 9, 16, 18, 24].iter() {
+      let mut data = vec![0; *len];
+      data[0] = 3;
+      assert_eq!(
+        AppInstruction::unpack(&data),
+        Err(AppError::InvalidInstruction.into())
+      );
+    }
+  }
+
+  #[test]
+  fn unpack_rejects_a_payload_past_the_longest_instruction() {
+    // The longest instruction fits exactly
+    let mut data = vec![0; MAX_INSTRUCTION_LEN];
+    assert_eq!(
+      AppInstruction::unpack(&data),
+      Ok(AppInstruction::InitializePool {
+        reserve_s: 0,
+        reserve_a: 0,
+        reserve_b: 0
+      })
+    );
+    data.push(0);
+    for tag in 0..=16 {
+      data[0] = tag;
+      assert_eq!(
+        AppInstruction::unpack(&data),
+        Err(AppError::InvalidInstruction.into())
+      );
+    }
+  }
+
+  #[test]
+  fn unpack_rejects_every_truncated_field() {
+    use AppInstruction::*;
+    // Each tag with u64 fields, read from the words 1, 2, 3 in order
+    let table = [
+      (
+        0,
+        3,
+        InitializePool {
+          reserve_s: 1,
+          reserve_a: 2,
+          reserve_b: 3,
+        },
+      ),
+      (
+        1,
+        3,
+        AddLiquidity {
+          delta_s: 1,
+          delta_a: 2,
+          delta_b: 3,
+        },
+      ),
+      (2, 1, RemoveLiquidity { lpt: 1 }),
+      (
+        3,
+        2,
+        Swap {
+          amount: 1,
+          limit: 2,
+          slot: None,
+        },
+      ),
+      (6, 1, Earn { amount: 1 }),
+      (11, 1, SetMaxLptSupply { max_lpt_supply: 1 }),
+      (
+        12,
+        2,
+        SwapFromSol {
+          amount: 1,
+          limit: 2,
+        },
+      ),
+      (
+        14,
+        3,
+        SetMinReserve {
+          min_reserve_s: 1,
+          min_reserve_a: 2,
+          min_reserve_b: 3,
+        },
+      ),
+    ];
+    for (tag, fields, expected) in table.iter() {
+      let mut data = vec![*tag];
+      for word in 1..=*fields {
+        data.extend_from_slice(&(word as u64).to_le_bytes());
+      }
+      assert_eq!(AppInstruction::unpack(&data), Ok(expected.clone()));
+      for len in 1..data.len() {
+        assert_eq!(
+          AppInstruction::unpack(&data[..len]),
+          Err(AppError::InvalidInstruction.into()),
+          "tag {} cut to {} bytes",
+          tag,
+          len
+        );
+      }
+    }
+  }
 }
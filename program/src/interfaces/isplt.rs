@@ -1,10 +1,14 @@
 use solana_program::{
+  declare_id,
   instruction::{AccountMeta, Instruction},
   program_error::ProgramError,
   pubkey::Pubkey,
 };
 use std::mem::size_of;
 
+// SPL token program
+declare_id!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+
 pub struct ISPLT {}
 
 impl ISPLT {
@@ -2,3 +2,4 @@ pub mod isplata;
 pub mod isplt;
 pub mod xsplata;
 pub mod xsplt;
+pub mod xsystem;
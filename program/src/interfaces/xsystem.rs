@@ -0,0 +1,34 @@
+use solana_program::{
+  account_info::AccountInfo, entrypoint::ProgramResult, program::invoke_signed, pubkey::Pubkey,
+  system_instruction,
+};
+
+pub struct XSYSTEM {}
+
+impl XSYSTEM {
+  ///
+  /// Create account
+  ///
+  pub fn create_account<'a>(
+    lamports: u64,
+    space: u64,
+    owner: &Pubkey,
+    funding_acc: &AccountInfo<'a>,
+    target_acc: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    seed: &[&[&[u8]]],
+  ) -> ProgramResult {
+    let ix =
+      system_instruction::create_account(funding_acc.key, target_acc.key, lamports, space, owner);
+    invoke_signed(
+      &ix,
+      &[
+        funding_acc.clone(),
+        target_acc.clone(),
+        system_program.clone(),
+      ],
+      seed,
+    )?;
+    Ok(())
+  }
+}
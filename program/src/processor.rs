@@ -1,8 +1,14 @@
 use crate::error::AppError;
-use crate::helper::{oracle::Oracle, pubutil::Boolean};
+use crate::helper::{
+  acl::{require_pool_owner, require_treasury_authority},
+  math::MulDiv,
+  oracle::{Oracle, DECIMALS, SEN_FLOOR},
+  validate::validate_pool_accounts,
+};
 use crate::instruction::AppInstruction;
-use crate::interfaces::{xsplata::XSPLATA, xsplt::XSPLT};
+use crate::interfaces::{isplt, xsplata::XSPLATA, xsplt::XSPLT, xsystem::XSYSTEM};
 use crate::schema::{
+  account::Account,
   mint::Mint,
   pool::{Pool, PoolState},
 };
@@ -10,10 +16,14 @@ use solana_program::{
   account_info::{next_account_info, AccountInfo},
   entrypoint::ProgramResult,
   msg,
+  program_error::ProgramError,
   program_pack::{IsInitialized, Pack},
   pubkey::{Pubkey, PubkeyError},
+  sysvar::{clock::Clock, rent::Rent, Sysvar},
 };
 
+const MAX_QUOTE_AGE: u64 = 150; // slots, about one minute
+
 pub struct Processor {}
 
 impl Processor {
@@ -50,9 +60,13 @@ impl Processor {
         Self::remove_liquidity(lpt, program_id, accounts)
       }
 
-      AppInstruction::Swap { amount, limit } => {
+      AppInstruction::Swap {
+        amount,
+        limit,
+        slot,
+      } => {
         msg!("Calling Swap function");
-        Self::swap(amount, limit, program_id, accounts)
+        Self::swap(amount, limit, slot, program_id, accounts)
       }
 
       AppInstruction::FreezePool {} => {
@@ -74,6 +88,61 @@ impl Processor {
         msg!("Calling TransferPoolOwnership function");
         Self::transfer_pool_ownership(program_id, accounts)
       }
+
+      AppInstruction::TransferAndClose => {
+        msg!("Calling TransferAndClose function");
+        Self::transfer_and_close(program_id, accounts)
+      }
+
+      AppInstruction::SetEarningMode { accrue } => {
+        msg!("Calling SetEarningMode function");
+        Self::set_earning_mode(accrue, program_id, accounts)
+      }
+
+      AppInstruction::SweepEarning => {
+        msg!("Calling SweepEarning function");
+        Self::sweep_earning(program_id, accounts)
+      }
+
+      AppInstruction::SetMaxLptSupply { max_lpt_supply } => {
+        msg!("Calling SetMaxLptSupply function");
+        Self::set_max_lpt_supply(max_lpt_supply, program_id, accounts)
+      }
+
+      AppInstruction::SwapFromSol { amount, limit } => {
+        msg!("Calling SwapFromSol function");
+        Self::swap_from_sol(amount, limit, program_id, accounts)
+      }
+
+      AppInstruction::ReconcileReserve => {
+        msg!("Calling ReconcileReserve function");
+        Self::reconcile_reserve(program_id, accounts)
+      }
+
+      AppInstruction::SetMinReserve {
+        min_reserve_s,
+        min_reserve_a,
+        min_reserve_b,
+      } => {
+        msg!("Calling SetMinReserve function");
+        Self::set_min_reserve(
+          min_reserve_s,
+          min_reserve_a,
+          min_reserve_b,
+          program_id,
+          accounts,
+        )
+      }
+
+      AppInstruction::SetTreasuryAuthority => {
+        msg!("Calling SetTreasuryAuthority function");
+        Self::set_treasury_authority(program_id, accounts)
+      }
+
+      AppInstruction::SetVault => {
+        msg!("Calling SetVault function");
+        Self::set_vault(program_id, accounts)
+      }
     }
   }
 
@@ -124,12 +193,16 @@ impl Processor {
     if pool_data.is_initialized() || mint_lpt_data.is_initialized() {
       return Err(AppError::ConstructorOnce.into());
     }
-    if *proof_acc.key != program_id.xor(&(pool_acc.key.xor(treasurer.key)))
-      || *mint_s_acc.key == *mint_a_acc.key
-      || *mint_s_acc.key == *mint_b_acc.key
-    {
-      return Err(AppError::InvalidMint.into());
-    }
+    validate_pool_accounts(
+      program_id,
+      pool_acc.key,
+      treasurer.key,
+      proof_acc.key,
+      mint_s_acc.key,
+      mint_a_acc.key,
+      mint_b_acc.key,
+    )
+    .map_err(|errors| errors[0].clone())?;
     if reserve_s == 0 || reserve_a == 0 || reserve_b == 0 {
       return Err(AppError::ZeroValue.into());
     }
@@ -289,9 +362,23 @@ impl Processor {
 
     Self::is_program(program_id, &[pool_acc])?;
     Self::is_signer(&[owner])?;
+    // Sources of a zero delta are never debited, so they may be any account
+    let mut distinct = vec![lpt_acc, treasury_s_acc, treasury_a_acc, treasury_b_acc];
+    for (delta, src_acc) in [
+      (delta_s, src_s_acc),
+      (delta_a, src_a_acc),
+      (delta_b, src_b_acc),
+    ]
+    .iter()
+    {
+      if *delta > 0 {
+        distinct.push(src_acc);
+      }
+    }
+    Self::is_distinct(&distinct)?;
 
     let mint_lpt_data = Mint::unpack(&mint_lpt_acc.data.borrow())?;
-    let mut pool_data = Pool::unpack(&pool_acc.data.borrow())?;
+    let mut pool_data = Pool::unpack_versioned(&pool_acc.data.borrow())?;
     let seed: &[&[&[u8]]] = &[&[&Self::safe_seed(pool_acc, treasurer, program_id)?[..]]];
     if pool_data.mint_lpt != *mint_lpt_acc.key
       || pool_data.treasury_s != *treasury_s_acc.key
@@ -300,9 +387,23 @@ impl Processor {
     {
       return Err(AppError::InvalidOwner.into());
     }
+    Self::validate_treasury(&pool_data, treasury_s_acc, treasurer.key, true)?;
+    Self::validate_treasury(&pool_data, treasury_a_acc, treasurer.key, true)?;
+    Self::validate_treasury(&pool_data, treasury_b_acc, treasurer.key, true)?;
+    // LPT minted to the treasurer would be locked in the pool forever
+    let lpt_data = Account::unpack(&lpt_acc.data.borrow())?;
+    if lpt_data.owner == *treasurer.key {
+      return Err(AppError::InvalidOwner.into());
+    }
     if delta_s == 0 && delta_a == 0 && delta_b == 0 {
       return Err(AppError::ZeroValue.into());
     }
+    if pool_data.reserve_s.checked_add(delta_s).is_none()
+      || pool_data.reserve_a.checked_add(delta_a).is_none()
+      || pool_data.reserve_b.checked_add(delta_b).is_none()
+    {
+      return Err(AppError::PoolFull.into());
+    }
 
     let (lpt, reserve_s, reserve_a, reserve_b) = Oracle::rake(
       delta_s,
@@ -314,6 +415,14 @@ impl Processor {
       mint_lpt_data.supply,
     )
     .ok_or(AppError::Overflow)?;
+    let new_lpt_supply = mint_lpt_data
+      .supply
+      .checked_add(lpt)
+      .ok_or(AppError::LptSupplyFull)?;
+    // A zero cap, as on pools created before the cap existed, means no cap
+    if pool_data.max_lpt_supply != 0 && new_lpt_supply > pool_data.max_lpt_supply {
+      return Err(AppError::ExceedLimit.into());
+    }
 
     // Deposit token
     if delta_s > 0 {
@@ -329,7 +438,7 @@ impl Processor {
       pool_data.reserve_b = reserve_b;
     }
     // Update pool
-    Pool::pack(pool_data, &mut pool_acc.data.borrow_mut())?;
+    Pool::pack_versioned(pool_data, &mut pool_acc.data.borrow_mut())?;
     // Mint LPT
     XSPLT::mint_to(lpt, mint_lpt_acc, lpt_acc, treasurer, splt_program, seed)?;
 
@@ -361,10 +470,19 @@ impl Processor {
 
     Self::is_program(program_id, &[pool_acc])?;
     Self::is_signer(&[owner])?;
+    Self::is_distinct(&[
+      lpt_acc,
+      dst_s_acc,
+      treasury_s_acc,
+      dst_a_acc,
+      treasury_a_acc,
+      dst_b_acc,
+      treasury_b_acc,
+    ])?;
     let seed: &[&[&[u8]]] = &[&[&Self::safe_seed(pool_acc, treasurer, program_id)?[..]]];
 
     let mint_lpt_data = Mint::unpack(&mint_lpt_acc.data.borrow())?;
-    let mut pool_data = Pool::unpack(&pool_acc.data.borrow())?;
+    let mut pool_data = Pool::unpack_versioned(&pool_acc.data.borrow())?;
     if pool_data.mint_lpt != *mint_lpt_acc.key
       || pool_data.treasury_s != *treasury_s_acc.key
       || pool_data.treasury_a != *treasury_a_acc.key
@@ -372,6 +490,10 @@ impl Processor {
     {
       return Err(AppError::UnmatchedPool.into());
     }
+    // A drifted balance must not hold exits hostage, the payout transfer fails if it cannot be met
+    Self::validate_treasury(&pool_data, treasury_s_acc, treasurer.key, false)?;
+    Self::validate_treasury(&pool_data, treasury_a_acc, treasurer.key, false)?;
+    Self::validate_treasury(&pool_data, treasury_b_acc, treasurer.key, false)?;
     if pool_data.is_frozen() {
       return Err(AppError::FrozenPool.into());
     }
@@ -379,21 +501,15 @@ impl Processor {
       return Err(AppError::ZeroValue.into());
     }
 
-    // Compute corresponding paid-back reserve
+    // Compute corresponding paid-back reserve, rounded down in favor of the pool
     let delta_s = (lpt as u128)
-      .checked_mul(pool_data.reserve_s as u128)
-      .ok_or(AppError::Overflow)?
-      .checked_div(mint_lpt_data.supply as u128)
+      .mul_div(pool_data.reserve_s as u128, mint_lpt_data.supply as u128)
       .ok_or(AppError::Overflow)? as u64;
     let delta_a = (lpt as u128)
-      .checked_mul(pool_data.reserve_a as u128)
-      .ok_or(AppError::Overflow)?
-      .checked_div(mint_lpt_data.supply as u128)
+      .mul_div(pool_data.reserve_a as u128, mint_lpt_data.supply as u128)
       .ok_or(AppError::Overflow)? as u64;
     let delta_b = (lpt as u128)
-      .checked_mul(pool_data.reserve_b as u128)
-      .ok_or(AppError::Overflow)?
-      .checked_div(mint_lpt_data.supply as u128)
+      .mul_div(pool_data.reserve_b as u128, mint_lpt_data.supply as u128)
       .ok_or(AppError::Overflow)? as u64;
     // Burn LPT
     XSPLT::burn(lpt, lpt_acc, mint_lpt_acc, owner, splt_program, seed)?;
@@ -413,7 +529,7 @@ impl Processor {
     if pool_data.reserve_s == 0 {
       pool_data.state = PoolState::Frozen;
     }
-    Pool::pack(pool_data, &mut pool_acc.data.borrow_mut())?;
+    Pool::pack_versioned(pool_data, &mut pool_acc.data.borrow_mut())?;
     // Withdraw token
     XSPLT::transfer(
       delta_s,
@@ -446,6 +562,7 @@ impl Processor {
   pub fn swap(
     amount: u64,
     limit: u64,
+    slot: Option<u64>,
     program_id: &Pubkey,
     accounts: &[AccountInfo],
   ) -> ProgramResult {
@@ -467,8 +584,11 @@ impl Processor {
 
     Self::is_program(program_id, &[pool_acc])?;
     Self::is_signer(&[payer])?;
+    Self::is_distinct(&[src_acc, dst_acc, vault_acc, treasury_bid_acc])?;
+    Self::is_distinct(&[src_acc, dst_acc, vault_acc, treasury_ask_acc])?;
+    Self::is_distinct(&[src_acc, dst_acc, vault_acc, treasury_sen_acc])?;
 
-    let mut pool_data = Pool::unpack(&pool_acc.data.borrow())?;
+    let mut pool_data = Pool::unpack_versioned(&pool_acc.data.borrow())?;
     let seed: &[&[&[u8]]] = &[&[&Self::safe_seed(pool_acc, treasurer, program_id)?[..]]];
     let (bid_code, bid_reserve) = pool_data
       .get_reserve(treasury_bid_acc.key)
@@ -479,27 +599,51 @@ impl Processor {
     let (sen_code, _) = pool_data
       .get_reserve(treasury_sen_acc.key)
       .ok_or(AppError::UnmatchedPool)?;
-    if sen_code != 0 {
+    if sen_code != 0 || pool_data.vault != *vault_acc.key {
       return Err(AppError::UnmatchedPool.into());
     }
+    Self::validate_treasury(&pool_data, treasury_bid_acc, treasurer.key, true)?;
+    Self::validate_treasury(&pool_data, treasury_ask_acc, treasurer.key, true)?;
+    Self::validate_treasury(&pool_data, treasury_sen_acc, treasurer.key, true)?;
     if pool_data.is_frozen() {
       return Err(AppError::FrozenPool.into());
     }
     if amount == 0 {
       return Err(AppError::ZeroValue.into());
     }
+    // Reject amounts the bid reserve could never hold
+    let new_bid_reserve = bid_reserve
+      .checked_add(amount)
+      .ok_or(AppError::ExcessiveSwap)?;
     if *treasury_bid_acc.key == *treasury_ask_acc.key {
       return Ok(());
     }
+    if let Some(slot) = slot {
+      let clock = Clock::get()?;
+      // A quote cannot be made in a slot that has not happened yet
+      if slot > clock.slot {
+        return Err(AppError::InvalidInstruction.into());
+      }
+      if clock.slot - slot > MAX_QUOTE_AGE {
+        return Err(AppError::Expired.into());
+      }
+    }
 
     // Compute new state
-    let new_bid_reserve = bid_reserve.checked_add(amount).ok_or(AppError::Overflow)?;
     let (new_ask_reserve, paid_amount, earning) =
-      Oracle::curve_in_fee(new_bid_reserve, bid_reserve, ask_reserve, ask_code == 0)
-        .ok_or(AppError::Overflow)?;
+      Oracle::curve_in_fee(new_bid_reserve, bid_reserve, ask_reserve, ask_code == 0)?;
     if paid_amount < limit {
       return Err(AppError::ExceedLimit.into());
     }
+    let min_reserve = match ask_code {
+      0 => pool_data.min_reserve_s,
+      1 => pool_data.min_reserve_a,
+      2 => pool_data.min_reserve_b,
+      _ => return Err(AppError::UnmatchedPool.into()),
+    };
+    if new_ask_reserve < min_reserve {
+      return Err(AppError::BelowMinReserve.into());
+    }
 
     // Transfer bid
     XSPLT::transfer(amount, src_acc, treasury_bid_acc, payer, splt_program, &[])?;
@@ -525,6 +669,12 @@ impl Processor {
       splt_program,
       seed,
     )?;
+    msg!(
+      "Paid: {}, limit: {}, binding: {}",
+      paid_amount,
+      limit,
+      paid_amount == limit
+    );
 
     // Execute earning
     if earning != 0 {
@@ -532,32 +682,71 @@ impl Processor {
       let new_ask_reserve_with_earning = new_ask_reserve
         .checked_add(earning)
         .ok_or(AppError::Overflow)?;
-      let (new_sen_reserve, earning_in_sen, _) = Oracle::curve_in_fee(
-        new_ask_reserve_with_earning, // with earning
-        new_ask_reserve,              // without earning
-        pool_data.reserve_s,
-        true,
-      )
-      .ok_or(AppError::Overflow)?;
       match ask_code {
         1 => pool_data.reserve_a = new_ask_reserve_with_earning,
         2 => pool_data.reserve_b = new_ask_reserve_with_earning,
         _ => return Err(AppError::UnmatchedPool.into()),
       }
-      pool_data.reserve_s = new_sen_reserve;
-      // Transfer earning
-      XSPLT::transfer(
-        earning_in_sen,
-        treasury_sen_acc,
-        vault_acc,
-        treasurer,
-        splt_program,
-        seed,
+      // An empty S reserve cannot buy back the earning
+      if pool_data.reserve_s == 0 {
+        return Err(AppError::InsufficientFunds.into());
+      }
+      let sen_floor = (pool_data.reserve_s as u128)
+        .mul_div(SEN_FLOOR as u128, DECIMALS as u128)
+        .ok_or(AppError::Overflow)? as u64;
+      let (new_sen_reserve, earning_in_sen, _) = Oracle::curve_in_fee(
+        new_ask_reserve_with_earning, // with earning
+        new_ask_reserve,              // without earning
+        pool_data.reserve_s,
+        true,
       )?;
+      if new_sen_reserve >= sen_floor {
+        pool_data.reserve_s = new_sen_reserve;
+        if pool_data.accrue_earning {
+          // Keep earning in the S treasury until the next sweep
+          pool_data.pending_earning = pool_data
+            .pending_earning
+            .checked_add(earning_in_sen)
+            .ok_or(AppError::Overflow)?;
+        } else {
+          // Transfer earning
+          XSPLT::transfer(
+            earning_in_sen,
+            treasury_sen_acc,
+            vault_acc,
+            treasurer,
+            splt_program,
+            seed,
+          )?;
+        }
+      } else {
+        // The S reserve is too thin to buy back the earning, so leave it in the ask reserve
+        msg!("Warning: Skip earning to protect the S reserve");
+      }
+    } else if ask_code == 0 {
+      msg!("No earning: the ask is S");
+    } else {
+      msg!("No earning: the swap is too small");
     }
 
+    // The recorded ask reserve must still be backed by the treasury
+    let treasury_ask_data = Account::unpack(&treasury_ask_acc.data.borrow())?;
+    let (_, recorded_ask_reserve) = pool_data
+      .get_reserve(treasury_ask_acc.key)
+      .ok_or(AppError::UnmatchedPool)?;
+    if recorded_ask_reserve > treasury_ask_data.amount {
+      return Err(AppError::InvariantViolation.into());
+    }
+
+    // Track activity
+    pool_data.swap_count = pool_data
+      .swap_count
+      .checked_add(1)
+      .ok_or(AppError::Overflow)?;
+    pool_data.last_swap_slot = Clock::get()?.slot;
+
     // Save final data
-    Pool::pack(pool_data, &mut pool_acc.data.borrow_mut())?;
+    Pool::pack_versioned(pool_data, &mut pool_acc.data.borrow_mut())?;
 
     Ok(())
   }
@@ -568,12 +757,11 @@ impl Processor {
     let pool_acc = next_account_info(accounts_iter)?;
 
     Self::is_program(program_id, &[pool_acc])?;
-    Self::is_signer(&[owner])?;
-    Self::is_pool_owner(owner, pool_acc)?;
 
-    let mut pool_data = Pool::unpack(&pool_acc.data.borrow())?;
+    let mut pool_data = Pool::unpack_versioned(&pool_acc.data.borrow())?;
+    require_pool_owner(owner, &pool_data)?;
     pool_data.state = PoolState::Frozen;
-    Pool::pack(pool_data, &mut pool_acc.data.borrow_mut())?;
+    Pool::pack_versioned(pool_data, &mut pool_acc.data.borrow_mut())?;
 
     Ok(())
   }
@@ -584,12 +772,11 @@ impl Processor {
     let pool_acc = next_account_info(accounts_iter)?;
 
     Self::is_program(program_id, &[pool_acc])?;
-    Self::is_signer(&[owner])?;
-    Self::is_pool_owner(owner, pool_acc)?;
 
-    let mut pool_data = Pool::unpack(&pool_acc.data.borrow())?;
+    let mut pool_data = Pool::unpack_versioned(&pool_acc.data.borrow())?;
+    require_pool_owner(owner, &pool_data)?;
     pool_data.state = PoolState::Initialized;
-    Pool::pack(pool_data, &mut pool_acc.data.borrow_mut())?;
+    Pool::pack_versioned(pool_data, &mut pool_acc.data.borrow_mut())?;
 
     Ok(())
   }
@@ -604,10 +791,10 @@ impl Processor {
     let splt_program = next_account_info(accounts_iter)?;
 
     Self::is_program(program_id, &[pool_acc])?;
-    Self::is_signer(&[owner])?;
-    Self::is_pool_owner(owner, pool_acc)?;
+    Self::is_distinct(&[vault_acc, dst_acc])?;
 
-    let pool_data = Pool::unpack(&pool_acc.data.borrow())?;
+    let pool_data = Pool::unpack_versioned(&pool_acc.data.borrow())?;
+    require_pool_owner(owner, &pool_data)?;
     let seed: &[&[&[u8]]] = &[&[&Self::safe_seed(pool_acc, treasurer, program_id)?[..]]];
     if pool_data.vault != *vault_acc.key {
       return Err(AppError::InvalidOwner.into());
@@ -628,13 +815,362 @@ impl Processor {
     let new_owner = next_account_info(accounts_iter)?;
 
     Self::is_program(program_id, &[pool_acc])?;
-    Self::is_signer(&[owner])?;
-    Self::is_pool_owner(owner, pool_acc)?;
+
+    let mut pool_data = Pool::unpack_versioned(&pool_acc.data.borrow())?;
+    require_pool_owner(owner, &pool_data)?;
 
     // Update pool data
-    let mut pool_data = Pool::unpack(&pool_acc.data.borrow())?;
     pool_data.owner = *new_owner.key;
-    Pool::pack(pool_data, &mut pool_acc.data.borrow_mut())?;
+    Pool::pack_versioned(pool_data, &mut pool_acc.data.borrow_mut())?;
+
+    Ok(())
+  }
+
+  pub fn transfer_and_close(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let owner = next_account_info(accounts_iter)?;
+    let pool_acc = next_account_info(accounts_iter)?;
+    let src_lpt_acc = next_account_info(accounts_iter)?;
+    let dst_lpt_acc = next_account_info(accounts_iter)?;
+    let splt_program = next_account_info(accounts_iter)?;
+
+    Self::is_program(program_id, &[pool_acc])?;
+    Self::is_signer(&[owner])?;
+    // Transferring to itself would close the position it just received
+    if *src_lpt_acc.key == *dst_lpt_acc.key {
+      return Err(AppError::SamedLpt.into());
+    }
+
+    let pool_data = Pool::unpack_versioned(&pool_acc.data.borrow())?;
+    let src_lpt_data = Account::unpack(&src_lpt_acc.data.borrow())?;
+    let dst_lpt_data = Account::unpack(&dst_lpt_acc.data.borrow())?;
+    if src_lpt_data.mint != pool_data.mint_lpt || dst_lpt_data.mint != pool_data.mint_lpt {
+      return Err(AppError::UnmatchedPool.into());
+    }
+    if src_lpt_data.owner != *owner.key {
+      return Err(AppError::InvalidOwner.into());
+    }
+    if src_lpt_data.is_frozen() || dst_lpt_data.is_frozen() {
+      return Err(AppError::Frozen.into());
+    }
+    if src_lpt_data.delegate.is_some() && src_lpt_data.delegated_amount > 0 {
+      return Err(AppError::DelegateStillActive.into());
+    }
+
+    // Transfer the whole position
+    if src_lpt_data.amount > 0 {
+      XSPLT::transfer(
+        src_lpt_data.amount,
+        src_lpt_acc,
+        dst_lpt_acc,
+        owner,
+        splt_program,
+        &[],
+      )?;
+    }
+    // Close the source and give its rent back to the owner
+    XSPLT::close_account(src_lpt_acc, owner, owner, splt_program, &[])?;
+
+    Ok(())
+  }
+
+  pub fn set_earning_mode(
+    accrue: bool,
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let owner = next_account_info(accounts_iter)?;
+    let pool_acc = next_account_info(accounts_iter)?;
+
+    Self::is_program(program_id, &[pool_acc])?;
+
+    let mut pool_data = Pool::unpack_versioned(&pool_acc.data.borrow())?;
+    require_pool_owner(owner, &pool_data)?;
+
+    // Update pool data
+    pool_data.accrue_earning = accrue;
+    Pool::pack_versioned(pool_data, &mut pool_acc.data.borrow_mut())?;
+
+    Ok(())
+  }
+
+  pub fn sweep_earning(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let pool_acc = next_account_info(accounts_iter)?;
+    let vault_acc = next_account_info(accounts_iter)?;
+    let treasury_sen_acc = next_account_info(accounts_iter)?;
+    let treasurer = next_account_info(accounts_iter)?;
+    let splt_program = next_account_info(accounts_iter)?;
+
+    Self::is_program(program_id, &[pool_acc])?;
+
+    let mut pool_data = Pool::unpack_versioned(&pool_acc.data.borrow())?;
+    let seed: &[&[&[u8]]] = &[&[&Self::safe_seed(pool_acc, treasurer, program_id)?[..]]];
+    if pool_data.vault != *vault_acc.key || pool_data.treasury_s != *treasury_sen_acc.key {
+      return Err(AppError::UnmatchedPool.into());
+    }
+    let amount = pool_data.pending_earning;
+    if amount == 0 {
+      return Err(AppError::ZeroValue.into());
+    }
+
+    // Update pool data
+    pool_data.pending_earning = 0;
+    Pool::pack_versioned(pool_data, &mut pool_acc.data.borrow_mut())?;
+    // Transfer accrued earning
+    XSPLT::transfer(
+      amount,
+      treasury_sen_acc,
+      vault_acc,
+      treasurer,
+      splt_program,
+      seed,
+    )?;
+
+    Ok(())
+  }
+
+  pub fn set_max_lpt_supply(
+    max_lpt_supply: u64,
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let owner = next_account_info(accounts_iter)?;
+    let pool_acc = next_account_info(accounts_iter)?;
+
+    Self::is_program(program_id, &[pool_acc])?;
+
+    let mut pool_data = Pool::unpack_versioned(&pool_acc.data.borrow())?;
+    require_pool_owner(owner, &pool_data)?;
+
+    // Update pool data, zero lifts the cap
+    pool_data.max_lpt_supply = max_lpt_supply;
+    Pool::pack_versioned(pool_data, &mut pool_acc.data.borrow_mut())?;
+
+    Ok(())
+  }
+
+  pub fn swap_from_sol(
+    amount: u64,
+    limit: u64,
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let payer = next_account_info(accounts_iter)?;
+    let pool_acc = next_account_info(accounts_iter)?;
+    let vault_acc = next_account_info(accounts_iter)?;
+
+    let wsol_acc = next_account_info(accounts_iter)?; // A fresh account, closed at the end
+    let mint_wsol_acc = next_account_info(accounts_iter)?;
+    let treasury_bid_acc = next_account_info(accounts_iter)?;
+
+    let dst_acc = next_account_info(accounts_iter)?;
+    let treasury_ask_acc = next_account_info(accounts_iter)?;
+
+    let treasury_sen_acc = next_account_info(accounts_iter)?;
+
+    let treasurer = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+    let splt_program = next_account_info(accounts_iter)?;
+    let sysvar_rent_acc = next_account_info(accounts_iter)?;
+
+    // Check everything the wrapping relies on before creating any account
+    Self::is_program(program_id, &[pool_acc])?;
+    Self::is_signer(&[payer, wsol_acc])?;
+    if *splt_program.key != isplt::id() {
+      return Err(AppError::IncorrectProgramId.into());
+    }
+    if amount == 0 {
+      return Err(AppError::ZeroValue.into());
+    }
+
+    // Wrap SOL into a temporary account
+    let rent = Rent::from_account_info(sysvar_rent_acc)?;
+    let lamports = rent
+      .minimum_balance(Account::LEN)
+      .checked_add(amount)
+      .ok_or(AppError::Overflow)?;
+    XSYSTEM::create_account(
+      lamports,
+      Account::LEN as u64,
+      splt_program.key,
+      payer,
+      wsol_acc,
+      system_program,
+      &[],
+    )?;
+    XSPLT::initialize_account(
+      wsol_acc,
+      mint_wsol_acc,
+      payer,
+      sysvar_rent_acc,
+      splt_program,
+      &[],
+    )?;
+    let wsol_data = Account::unpack(&wsol_acc.data.borrow())?;
+    let treasury_bid_data = Account::unpack(&treasury_bid_acc.data.borrow())?;
+    if !wsol_data.is_native() || treasury_bid_data.mint != wsol_data.mint {
+      return Err(AppError::InvalidMint.into());
+    }
+
+    // Swap from the temporary account
+    Self::swap(
+      amount,
+      limit,
+      None,
+      program_id,
+      &[
+        payer.clone(),
+        pool_acc.clone(),
+        vault_acc.clone(),
+        wsol_acc.clone(),
+        treasury_bid_acc.clone(),
+        dst_acc.clone(),
+        treasury_ask_acc.clone(),
+        treasury_sen_acc.clone(),
+        treasurer.clone(),
+        splt_program.clone(),
+      ],
+    )?;
+    // Close the temporary account and give its rent back to the payer
+    XSPLT::close_account(wsol_acc, payer, payer, splt_program, &[])?;
+
+    Ok(())
+  }
+
+  pub fn reconcile_reserve(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let authority = next_account_info(accounts_iter)?;
+    let pool_acc = next_account_info(accounts_iter)?;
+    let treasury_s_acc = next_account_info(accounts_iter)?;
+    let treasury_a_acc = next_account_info(accounts_iter)?;
+    let treasury_b_acc = next_account_info(accounts_iter)?;
+    let treasurer = next_account_info(accounts_iter)?;
+
+    Self::is_program(program_id, &[pool_acc])?;
+
+    let mut pool_data = Pool::unpack_versioned(&pool_acc.data.borrow())?;
+    require_treasury_authority(authority, &pool_data)?;
+    Self::safe_seed(pool_acc, treasurer, program_id)?;
+    if pool_data.treasury_s != *treasury_s_acc.key
+      || pool_data.treasury_a != *treasury_a_acc.key
+      || pool_data.treasury_b != *treasury_b_acc.key
+    {
+      return Err(AppError::UnmatchedPool.into());
+    }
+    // A short balance is what gets repaired here
+    let treasury_s_data =
+      Self::validate_treasury(&pool_data, treasury_s_acc, treasurer.key, false)?;
+    let treasury_a_data =
+      Self::validate_treasury(&pool_data, treasury_a_acc, treasurer.key, false)?;
+    let treasury_b_data =
+      Self::validate_treasury(&pool_data, treasury_b_acc, treasurer.key, false)?;
+
+    // Reserves can only be aligned to what the treasuries really hold
+    let reserve_s = treasury_s_data
+      .amount
+      .checked_sub(pool_data.pending_earning)
+      .ok_or(AppError::Overflow)?;
+    let reserve_a = treasury_a_data.amount;
+    let reserve_b = treasury_b_data.amount;
+    msg!(
+      "Reconcile reserves: S {} -> {}, A {} -> {}, B {} -> {}",
+      pool_data.reserve_s,
+      reserve_s,
+      pool_data.reserve_a,
+      reserve_a,
+      pool_data.reserve_b,
+      reserve_b
+    );
+    // Update pool data
+    pool_data.reserve_s = reserve_s;
+    pool_data.reserve_a = reserve_a;
+    pool_data.reserve_b = reserve_b;
+    Pool::pack_versioned(pool_data, &mut pool_acc.data.borrow_mut())?;
+
+    Ok(())
+  }
+
+  pub fn set_min_reserve(
+    min_reserve_s: u64,
+    min_reserve_a: u64,
+    min_reserve_b: u64,
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+  ) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let owner = next_account_info(accounts_iter)?;
+    let pool_acc = next_account_info(accounts_iter)?;
+
+    Self::is_program(program_id, &[pool_acc])?;
+
+    let mut pool_data = Pool::unpack_versioned(&pool_acc.data.borrow())?;
+    require_pool_owner(owner, &pool_data)?;
+
+    // Update pool data
+    pool_data.min_reserve_s = min_reserve_s;
+    pool_data.min_reserve_a = min_reserve_a;
+    pool_data.min_reserve_b = min_reserve_b;
+    Pool::pack_versioned(pool_data, &mut pool_acc.data.borrow_mut())?;
+
+    Ok(())
+  }
+
+  pub fn set_treasury_authority(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let owner = next_account_info(accounts_iter)?;
+    let pool_acc = next_account_info(accounts_iter)?;
+    let treasury_authority = next_account_info(accounts_iter)?;
+
+    Self::is_program(program_id, &[pool_acc])?;
+
+    let mut pool_data = Pool::unpack_versioned(&pool_acc.data.borrow())?;
+    require_pool_owner(owner, &pool_data)?;
+
+    // Update pool data, the default key unsets the authority
+    pool_data.treasury_authority = *treasury_authority.key;
+    Pool::pack_versioned(pool_data, &mut pool_acc.data.borrow_mut())?;
+
+    Ok(())
+  }
+
+  pub fn set_vault(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+    let owner = next_account_info(accounts_iter)?;
+    let pool_acc = next_account_info(accounts_iter)?;
+    let vault_acc = next_account_info(accounts_iter)?;
+    let treasurer = next_account_info(accounts_iter)?;
+
+    Self::is_program(program_id, &[pool_acc])?;
+
+    let mut pool_data = Pool::unpack_versioned(&pool_acc.data.borrow())?;
+    require_pool_owner(owner, &pool_data)?;
+    Self::safe_seed(pool_acc, treasurer, program_id)?;
+    if *vault_acc.key == Pubkey::default() {
+      return Err(AppError::InvalidInstruction.into());
+    }
+    // A treasury would let Earn pay out the reserves it backs
+    if pool_data.get_reserve(vault_acc.key).is_some() {
+      return Err(AppError::InvalidInstruction.into());
+    }
+    // The new vault must be an SPL account holding S and stay spendable by Earn
+    if *vault_acc.owner != isplt::id() {
+      return Err(AppError::IncorrectProgramId.into());
+    }
+    let vault_data = Account::unpack(&vault_acc.data.borrow())?;
+    if vault_data.mint != pool_data.mint_s {
+      return Err(AppError::InvalidMint.into());
+    }
+    if vault_data.owner != *treasurer.key {
+      return Err(AppError::InvalidOwner.into());
+    }
+
+    // Update pool data
+    pool_data.vault = *vault_acc.key;
+    Pool::pack_versioned(pool_data, &mut pool_acc.data.borrow_mut())?;
 
     Ok(())
   }
@@ -655,20 +1191,55 @@ impl Processor {
   pub fn is_signer(accounts: &[&AccountInfo]) -> ProgramResult {
     for acc in &mut accounts.iter() {
       if !acc.is_signer {
-        return Err(AppError::InvalidOwner.into());
+        return Err(AppError::Unauthorized.into());
       }
     }
     Ok(())
   }
 
-  pub fn is_pool_owner(owner: &AccountInfo, pool_acc: &AccountInfo) -> ProgramResult {
-    let pool_data = Pool::unpack(&pool_acc.data.borrow())?;
-    if pool_data.owner != *owner.key {
-      return Err(AppError::InvalidOwner.into());
+  pub fn is_distinct(accounts: &[&AccountInfo]) -> ProgramResult {
+    for (i, acc) in accounts.iter().enumerate() {
+      for other in &accounts[i + 1..] {
+        if acc.key == other.key {
+          return Err(AppError::InvalidInstruction.into());
+        }
+      }
     }
     Ok(())
   }
 
+  pub fn validate_treasury(
+    pool_data: &Pool,
+    treasury_acc: &AccountInfo,
+    treasurer_key: &Pubkey,
+    check_balance: bool,
+  ) -> Result<Account, ProgramError> {
+    let (code, reserve) = pool_data
+      .get_reserve(treasury_acc.key)
+      .ok_or(AppError::UnmatchedPool)?;
+    let mint = match code {
+      0 => pool_data.mint_s,
+      1 => pool_data.mint_a,
+      2 => pool_data.mint_b,
+      _ => return Err(AppError::UnmatchedPool.into()),
+    };
+    let treasury_data = Account::unpack(&treasury_acc.data.borrow())?;
+    if treasury_data.mint != mint {
+      return Err(AppError::InvalidMint.into());
+    }
+    if treasury_data.owner != *treasurer_key {
+      return Err(AppError::InvalidOwner.into());
+    }
+    if treasury_data.is_frozen() {
+      return Err(AppError::Frozen.into());
+    }
+    // The treasury may hold more than the reserve (accrued earning) but never less
+    if check_balance && treasury_data.amount < reserve {
+      return Err(AppError::InsufficientFunds.into());
+    }
+    Ok(treasury_data)
+  }
+
   pub fn safe_seed(
     seed_acc: &AccountInfo,
     expected_acc: &AccountInfo,
@@ -682,3 +1253,6 @@ impl Processor {
     Ok(seed)
   }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,1807 @@
+use super::{Processor, MAX_QUOTE_AGE};
+use crate::error::AppError;
+use crate::helper::{oracle::Oracle, pubutil::Boolean};
+use crate::interfaces::isplt;
+use crate::schema::{
+  account::{Account, AccountState},
+  mint::Mint,
+  pool::{Pool, PoolState},
+};
+use solana_program::{
+  account_info::AccountInfo,
+  clock::Clock,
+  entrypoint::{ProgramResult, SUCCESS},
+  instruction::Instruction,
+  program_error::ProgramError,
+  program_option::COption,
+  program_pack::{IsInitialized, Pack},
+  program_stubs::{set_syscall_stubs, SyscallStubs},
+  pubkey::Pubkey,
+  rent::Rent,
+  system_program, sysvar,
+};
+use std::convert::TryInto;
+use std::str::FromStr;
+use std::sync::{Mutex, Once};
+
+///
+/// In-memory runtime: a fixed clock and the subset of SPL token the program calls
+///
+const PROGRAM_ID: Pubkey = Pubkey::new_from_array([7; 32]);
+const SLOT: u64 = 1_000;
+const RESERVE: u64 = 1_000_000_000_000;
+const OWNER_MISMATCH: u32 = 4; // spl_token::error::TokenError::OwnerMismatch
+
+fn native_mint_id() -> Pubkey {
+  Pubkey::from_str("So11111111111111111111111111111111111111112").unwrap()
+}
+
+// Exotic mints that charge the sender one extra unit per transfer
+static TAXED_MINTS: Mutex<Vec<Pubkey>> = Mutex::new(Vec::new());
+
+struct TestStubs;
+
+impl TestStubs {
+  fn find<'a, 'b>(
+    ix: &Instruction,
+    index: usize,
+    infos: &'b [AccountInfo<'a>],
+  ) -> Result<&'b AccountInfo<'a>, ProgramError> {
+    let key = ix
+      .accounts
+      .get(index)
+      .ok_or(ProgramError::NotEnoughAccountKeys)?
+      .pubkey;
+    infos
+      .iter()
+      .find(|info| *info.key == key)
+      .ok_or(ProgramError::NotEnoughAccountKeys)
+  }
+  // The authority signed the transaction or is a PDA of the program under test
+  fn signed(authority: &AccountInfo, seeds: &[&[&[u8]]]) -> ProgramResult {
+    let derived = seeds
+      .iter()
+      .any(|seed| Pubkey::create_program_address(seed, &PROGRAM_ID) == Ok(*authority.key));
+    if !authority.is_signer && !derived {
+      return Err(ProgramError::MissingRequiredSignature);
+    }
+    Ok(())
+  }
+  fn amount(ix: &Instruction) -> Result<u64, ProgramError> {
+    ix.data
+      .get(1..9)
+      .and_then(|slice| slice.try_into().ok())
+      .map(u64::from_le_bytes)
+      .ok_or(ProgramError::InvalidInstructionData)
+  }
+  fn token(ix: &Instruction, infos: &[AccountInfo], seeds: &[&[&[u8]]]) -> ProgramResult {
+    match ix.data.first() {
+      // InitializeAccount
+      Some(1) => {
+        let target = Self::find(ix, 0, infos)?;
+        let mint = Self::find(ix, 1, infos)?;
+        let owner = Self::find(ix, 2, infos)?;
+        let mut account = Account {
+          mint: *mint.key,
+          owner: *owner.key,
+          state: AccountState::Initialized,
+          ..Account::default()
+        };
+        if *mint.key == native_mint_id() {
+          let rent = Rent::default().minimum_balance(Account::LEN);
+          account.is_native = COption::Some(rent);
+          account.amount = target.lamports() - rent;
+        }
+        Account::pack(account, &mut target.data.borrow_mut())
+      }
+      // Transfer
+      Some(3) => {
+        let amount = Self::amount(ix)?;
+        let src = Self::find(ix, 0, infos)?;
+        let dst = Self::find(ix, 1, infos)?;
+        let authority = Self::find(ix, 2, infos)?;
+        let mut src_data = Account::unpack(&src.data.borrow())?;
+        let mut dst_data = Account::unpack(&dst.data.borrow())?;
+        if src_data.owner != *authority.key {
+          return Err(ProgramError::Custom(OWNER_MISMATCH));
+        }
+        Self::signed(authority, seeds)?;
+        if src_data.mint != dst_data.mint {
+          return Err(ProgramError::InvalidAccountData);
+        }
+        if src_data.is_frozen() || dst_data.is_frozen() {
+          return Err(ProgramError::InvalidAccountData);
+        }
+        let tax = TAXED_MINTS.lock().unwrap().contains(&src_data.mint) as u64;
+        src_data.amount = src_data
+          .amount
+          .checked_sub(amount + tax)
+          .ok_or(ProgramError::InsufficientFunds)?;
+        if src.key == dst.key {
+          return Ok(());
+        }
+        dst_data.amount = dst_data
+          .amount
+          .checked_add(amount)
+          .ok_or(ProgramError::InvalidArgument)?;
+        // Wrapped SOL is backed by the lamports themselves
+        if src_data.is_native() {
+          **src.lamports.borrow_mut() -= amount;
+          **dst.lamports.borrow_mut() += amount;
+        }
+        Account::pack(src_data, &mut src.data.borrow_mut())?;
+        Account::pack(dst_data, &mut dst.data.borrow_mut())
+      }
+      // MintTo
+      Some(7) => {
+        let amount = Self::amount(ix)?;
+        let mint = Self::find(ix, 0, infos)?;
+        let dst = Self::find(ix, 1, infos)?;
+        let authority = Self::find(ix, 2, infos)?;
+        let mut mint_data = Mint::unpack(&mint.data.borrow())?;
+        let mut dst_data = Account::unpack(&dst.data.borrow())?;
+        if mint_data.mint_authority != COption::Some(*authority.key) {
+          return Err(ProgramError::Custom(OWNER_MISMATCH));
+        }
+        Self::signed(authority, seeds)?;
+        if dst_data.mint != *mint.key {
+          return Err(ProgramError::InvalidAccountData);
+        }
+        mint_data.supply = mint_data
+          .supply
+          .checked_add(amount)
+          .ok_or(ProgramError::InvalidArgument)?;
+        dst_data.amount += amount;
+        Mint::pack(mint_data, &mut mint.data.borrow_mut())?;
+        Account::pack(dst_data, &mut dst.data.borrow_mut())
+      }
+      // Burn
+      Some(8) => {
+        let amount = Self::amount(ix)?;
+        let src = Self::find(ix, 0, infos)?;
+        let mint = Self::find(ix, 1, infos)?;
+        let authority = Self::find(ix, 2, infos)?;
+        let mut src_data = Account::unpack(&src.data.borrow())?;
+        let mut mint_data = Mint::unpack(&mint.data.borrow())?;
+        if src_data.owner != *authority.key {
+          return Err(ProgramError::Custom(OWNER_MISMATCH));
+        }
+        Self::signed(authority, seeds)?;
+        if src_data.mint != *mint.key {
+          return Err(ProgramError::InvalidAccountData);
+        }
+        src_data.amount = src_data
+          .amount
+          .checked_sub(amount)
+          .ok_or(ProgramError::InsufficientFunds)?;
+        mint_data.supply -= amount;
+        Account::pack(src_data, &mut src.data.borrow_mut())?;
+        Mint::pack(mint_data, &mut mint.data.borrow_mut())
+      }
+      // CloseAccount
+      Some(9) => {
+        let src = Self::find(ix, 0, infos)?;
+        let dst = Self::find(ix, 1, infos)?;
+        let authority = Self::find(ix, 2, infos)?;
+        let src_data = Account::unpack(&src.data.borrow())?;
+        if src_data.owner != *authority.key {
+          return Err(ProgramError::Custom(OWNER_MISMATCH));
+        }
+        Self::signed(authority, seeds)?;
+        if !src_data.is_native() && src_data.amount != 0 {
+          return Err(ProgramError::InvalidAccountData);
+        }
+        let lamports = src.lamports();
+        **dst.lamports.borrow_mut() += lamports;
+        **src.lamports.borrow_mut() = 0;
+        for byte in src.data.borrow_mut().iter_mut() {
+          *byte = 0;
+        }
+        Ok(())
+      }
+      _ => Err(ProgramError::InvalidInstructionData),
+    }
+  }
+  fn system(ix: &Instruction, infos: &[AccountInfo]) -> ProgramResult {
+    // CreateAccount { lamports, space, owner }, owners are fixed up front by the bench
+    if ix.data.get(0..4) != Some(&[0, 0, 0, 0]) {
+      return Err(ProgramError::InvalidInstructionData);
+    }
+    let lamports = u64::from_le_bytes(ix.data[4..12].try_into().unwrap());
+    let space = u64::from_le_bytes(ix.data[12..20].try_into().unwrap());
+    let from = Self::find(ix, 0, infos)?;
+    let to = Self::find(ix, 1, infos)?;
+    if !from.is_signer || !to.is_signer {
+      return Err(ProgramError::MissingRequiredSignature);
+    }
+    if to.lamports() != 0 || to.data_len() as u64 != space {
+      return Err(ProgramError::AccountAlreadyInitialized);
+    }
+    **from.lamports.borrow_mut() = from
+      .lamports()
+      .checked_sub(lamports)
+      .ok_or(ProgramError::InsufficientFunds)?;
+    **to.lamports.borrow_mut() = lamports;
+    Ok(())
+  }
+}
+
+impl SyscallStubs for TestStubs {
+  fn sol_log(&self, _message: &str) {}
+  fn sol_invoke_signed(
+    &self,
+    ix: &Instruction,
+    infos: &[AccountInfo],
+    seeds: &[&[&[u8]]],
+  ) -> ProgramResult {
+    if ix.program_id == isplt::id() {
+      return Self::token(ix, infos, seeds);
+    }
+    if ix.program_id == system_program::id() {
+      return Self::system(ix, infos);
+    }
+    Err(ProgramError::IncorrectProgramId)
+  }
+  fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+    let clock = Clock {
+      slot: SLOT,
+      ..Clock::default()
+    };
+    unsafe { *(var_addr as *mut Clock) = clock };
+    SUCCESS
+  }
+}
+
+fn setup() {
+  static INIT: Once = Once::new();
+  INIT.call_once(|| {
+    set_syscall_stubs(Box::new(TestStubs));
+  });
+}
+
+///
+/// Accounts of a live pool, owned by the test
+///
+struct TestAccount {
+  key: Pubkey,
+  owner: Pubkey,
+  lamports: u64,
+  data: Vec<u8>,
+  is_signer: bool,
+}
+
+struct Bench {
+  accounts: Vec<TestAccount>,
+  owner: Pubkey,
+  pool: Pubkey,
+  treasurer: Pubkey,
+  mint_lpt: Pubkey,
+  lpt: Pubkey,
+  vault: Pubkey,
+  mint_s: Pubkey,
+  mint_a: Pubkey,
+  mint_b: Pubkey,
+  treasury_s: Pubkey,
+  treasury_a: Pubkey,
+  treasury_b: Pubkey,
+  src_s: Pubkey,
+  src_a: Pubkey,
+  src_b: Pubkey,
+  splt: Pubkey,
+}
+
+impl Bench {
+  // A pool of RESERVE on every side, the owner holds all LPT and RESERVE of every token
+  fn new() -> Self {
+    setup();
+    let (pool, treasurer) = loop {
+      let pool = Pubkey::new_unique();
+      if let Ok(treasurer) = Pubkey::create_program_address(&[&pool.to_bytes()], &PROGRAM_ID) {
+        break (pool, treasurer);
+      }
+    };
+    let mut bench = Bench {
+      accounts: vec![],
+      owner: Pubkey::new_unique(),
+      pool,
+      treasurer,
+      mint_lpt: Pubkey::default(),
+      lpt: Pubkey::default(),
+      vault: Pubkey::default(),
+      mint_s: Pubkey::default(),
+      mint_a: Pubkey::default(),
+      mint_b: Pubkey::default(),
+      treasury_s: Pubkey::default(),
+      treasury_a: Pubkey::default(),
+      treasury_b: Pubkey::default(),
+      src_s: Pubkey::default(),
+      src_a: Pubkey::default(),
+      src_b: Pubkey::default(),
+      splt: isplt::id(),
+    };
+    bench.add(bench.owner, system_program::id(), 1_000_000_000, vec![]);
+    bench.signer(&bench.owner.clone(), true);
+    bench.add(bench.treasurer, system_program::id(), 0, vec![]);
+    bench.add(bench.splt, Pubkey::default(), 1, vec![]);
+    bench.mint_s = bench.add_mint(&bench.treasurer.clone(), 0);
+    bench.mint_a = bench.add_mint(&bench.treasurer.clone(), 0);
+    bench.mint_b = bench.add_mint(&bench.treasurer.clone(), 0);
+    bench.mint_lpt = bench.add_mint(&bench.treasurer.clone(), RESERVE);
+    let (owner, treasurer) = (bench.owner, bench.treasurer);
+    bench.lpt = bench.add_token(&bench.mint_lpt.clone(), &owner, RESERVE);
+    bench.vault = bench.add_token(&bench.mint_s.clone(), &treasurer, 0);
+    bench.treasury_s = bench.add_token(&bench.mint_s.clone(), &treasurer, RESERVE);
+    bench.treasury_a = bench.add_token(&bench.mint_a.clone(), &treasurer, RESERVE);
+    bench.treasury_b = bench.add_token(&bench.mint_b.clone(), &treasurer, RESERVE);
+    bench.src_s = bench.add_token(&bench.mint_s.clone(), &owner, RESERVE);
+    bench.src_a = bench.add_token(&bench.mint_a.clone(), &owner, RESERVE);
+    bench.src_b = bench.add_token(&bench.mint_b.clone(), &owner, RESERVE);
+    let pool_data = Pool {
+      owner,
+      state: PoolState::Initialized,
+      mint_lpt: bench.mint_lpt,
+      vault: bench.vault,
+      mint_s: bench.mint_s,
+      treasury_s: bench.treasury_s,
+      reserve_s: RESERVE,
+      mint_a: bench.mint_a,
+      treasury_a: bench.treasury_a,
+      reserve_a: RESERVE,
+      mint_b: bench.mint_b,
+      treasury_b: bench.treasury_b,
+      reserve_b: RESERVE,
+      ..Pool::default()
+    };
+    let mut data = vec![0; Pool::LEN];
+    Pool::pack(pool_data, &mut data).unwrap();
+    bench.add(pool, PROGRAM_ID, 1_000_000_000, data);
+    bench
+  }
+
+  fn add(&mut self, key: Pubkey, owner: Pubkey, lamports: u64, data: Vec<u8>) -> Pubkey {
+    self.accounts.push(TestAccount {
+      key,
+      owner,
+      lamports,
+      data,
+      is_signer: false,
+    });
+    key
+  }
+  fn add_mint(&mut self, authority: &Pubkey, supply: u64) -> Pubkey {
+    let mint = Mint {
+      mint_authority: COption::Some(*authority),
+      supply,
+      decimals: 9,
+      is_initialized: true,
+      freeze_authority: COption::None,
+    };
+    let mut data = vec![0; Mint::LEN];
+    Mint::pack(mint, &mut data).unwrap();
+    self.add(Pubkey::new_unique(), self.splt, 1_000_000, data)
+  }
+  fn add_token(&mut self, mint: &Pubkey, owner: &Pubkey, amount: u64) -> Pubkey {
+    let account = Account {
+      mint: *mint,
+      owner: *owner,
+      amount,
+      state: AccountState::Initialized,
+      ..Account::default()
+    };
+    let mut data = vec![0; Account::LEN];
+    Account::pack(account, &mut data).unwrap();
+    self.add(Pubkey::new_unique(), self.splt, 2_000_000, data)
+  }
+  fn add_user(&mut self) -> Pubkey {
+    let user = self.add(
+      Pubkey::new_unique(),
+      system_program::id(),
+      1_000_000_000,
+      vec![],
+    );
+    self.signer(&user, true);
+    user
+  }
+
+  fn account(&mut self, key: &Pubkey) -> &mut TestAccount {
+    self
+      .accounts
+      .iter_mut()
+      .find(|acc| acc.key == *key)
+      .expect("unknown account")
+  }
+  fn signer(&mut self, key: &Pubkey, is_signer: bool) {
+    self.account(key).is_signer = is_signer;
+  }
+  fn lamports(&mut self, key: &Pubkey) -> u64 {
+    self.account(key).lamports
+  }
+  fn token(&mut self, key: &Pubkey) -> Account {
+    Account::unpack(&self.account(key).data).unwrap()
+  }
+  fn set_token(&mut self, key: &Pubkey, update: impl FnOnce(&mut Account)) {
+    let mut data = self.token(key);
+    update(&mut data);
+    Account::pack(data, &mut self.account(key).data).unwrap();
+  }
+  fn pool(&mut self) -> Pool {
+    let pool = self.pool;
+    Pool::unpack_versioned(&self.account(&pool).data).unwrap()
+  }
+  fn set_pool(&mut self, update: impl FnOnce(&mut Pool)) {
+    let pool = self.pool;
+    let mut data = self.pool();
+    update(&mut data);
+    Pool::pack_versioned(data, &mut self.account(&pool).data).unwrap();
+  }
+  fn set_supply(&mut self, supply: u64) {
+    let mint_lpt = self.mint_lpt;
+    let mut data = Mint::unpack(&self.account(&mint_lpt).data).unwrap();
+    data.supply = supply;
+    Mint::pack(data, &mut self.account(&mint_lpt).data).unwrap();
+  }
+  fn supply(&mut self) -> u64 {
+    let mint_lpt = self.mint_lpt;
+    Mint::unpack(&self.account(&mint_lpt).data).unwrap().supply
+  }
+
+  // Run the instruction over the accounts in the given order, keys may repeat
+  // A failed instruction leaves every account as it was, like a failed transaction
+  fn run(&mut self, data: &[u8], keys: &[Pubkey]) -> ProgramResult {
+    let snapshot: Vec<(u64, Vec<u8>)> = self
+      .accounts
+      .iter()
+      .map(|acc| (acc.lamports, acc.data.clone()))
+      .collect();
+    let result = self.execute(data, keys);
+    if result.is_err() {
+      for (acc, (lamports, data)) in self.accounts.iter_mut().zip(snapshot) {
+        acc.lamports = lamports;
+        acc.data = data;
+      }
+    }
+    result
+  }
+  fn execute(&mut self, data: &[u8], keys: &[Pubkey]) -> ProgramResult {
+    let infos: Vec<AccountInfo> = self
+      .accounts
+      .iter_mut()
+      .map(|acc| {
+        AccountInfo::new(
+          &acc.key,
+          acc.is_signer,
+          true,
+          &mut acc.lamports,
+          &mut acc.data,
+          &acc.owner,
+          false,
+          0,
+        )
+      })
+      .collect();
+    let accounts: Vec<AccountInfo> = keys
+      .iter()
+      .map(|key| {
+        infos
+          .iter()
+          .find(|info| info.key == key)
+          .expect("unknown account")
+          .clone()
+      })
+      .collect();
+    Processor::process(&PROGRAM_ID, &accounts, data)
+  }
+
+  fn add_liquidity_keys(&self, lpt: &Pubkey) -> Vec<Pubkey> {
+    vec![
+      self.owner,
+      self.pool,
+      *lpt,
+      self.mint_lpt,
+      self.src_s,
+      self.treasury_s,
+      self.src_a,
+      self.treasury_a,
+      self.src_b,
+      self.treasury_b,
+      self.treasurer,
+      self.splt,
+    ]
+  }
+  fn remove_liquidity_keys(&self) -> Vec<Pubkey> {
+    vec![
+      self.owner,
+      self.pool,
+      self.lpt,
+      self.mint_lpt,
+      self.src_s,
+      self.treasury_s,
+      self.src_a,
+      self.treasury_a,
+      self.src_b,
+      self.treasury_b,
+      self.treasurer,
+      self.splt,
+    ]
+  }
+  fn swap_keys(
+    &self,
+    src: &Pubkey,
+    treasury_bid: &Pubkey,
+    dst: &Pubkey,
+    treasury_ask: &Pubkey,
+  ) -> Vec<Pubkey> {
+    vec![
+      self.owner,
+      self.pool,
+      self.vault,
+      *src,
+      *treasury_bid,
+      *dst,
+      *treasury_ask,
+      self.treasury_s,
+      self.treasurer,
+      self.splt,
+    ]
+  }
+  // Swap A for B, the pair that pays an earning
+  fn swap_a_to_b_keys(&self) -> Vec<Pubkey> {
+    self.swap_keys(&self.src_a, &self.treasury_a, &self.src_b, &self.treasury_b)
+  }
+  fn reconcile_reserve_keys(&self) -> Vec<Pubkey> {
+    vec![
+      self.owner,
+      self.pool,
+      self.treasury_s,
+      self.treasury_a,
+      self.treasury_b,
+      self.treasurer,
+    ]
+  }
+  fn transfer_and_close_keys(&self, src_lpt: &Pubkey, dst_lpt: &Pubkey) -> Vec<Pubkey> {
+    vec![self.owner, self.pool, *src_lpt, *dst_lpt, self.splt]
+  }
+}
+
+// Tag followed by little-endian u64 arguments
+fn ix(tag: u8, args: &[u64]) -> Vec<u8> {
+  let mut data = vec![tag];
+  for arg in args {
+    data.extend_from_slice(&arg.to_le_bytes());
+  }
+  data
+}
+
+fn err(error: AppError) -> ProgramResult {
+  Err(error.into())
+}
+
+///
+/// InitializePool
+///
+impl Bench {
+  // A fresh pool over the bench mints, paid for by the bench owner
+  fn initialize_pool_keys(&mut self, owner: &Pubkey) -> Vec<Pubkey> {
+    let (pool, treasurer) = loop {
+      let pool = Pubkey::new_unique();
+      if let Ok(treasurer) = Pubkey::create_program_address(&[&pool.to_bytes()], &PROGRAM_ID) {
+        break (pool, treasurer);
+      }
+    };
+    self.add(pool, PROGRAM_ID, 0, vec![0; Pool::LEN]);
+    self.signer(&pool, true);
+    self.add(treasurer, system_program::id(), 0, vec![]);
+    let vault = self.add_user();
+    let mint_lpt = self.add(Pubkey::new_unique(), self.splt, 0, vec![0; Mint::LEN]);
+    let lpt = self.add(Pubkey::new_unique(), self.splt, 0, vec![]);
+    let proof = self.add(
+      PROGRAM_ID.xor(&pool.xor(&treasurer)),
+      system_program::id(),
+      0,
+      vec![],
+    );
+    let mut treasuries = vec![];
+    for _ in 0..3 {
+      treasuries.push(self.add(Pubkey::new_unique(), self.splt, 0, vec![]));
+    }
+    let system = self.add(system_program::id(), Pubkey::default(), 1, vec![]);
+    let sysvar_rent = self.add(sysvar::rent::id(), Pubkey::default(), 1, vec![]);
+    let splata = self.add(Pubkey::new_unique(), Pubkey::default(), 1, vec![]);
+    vec![
+      self.owner,
+      *owner,
+      pool,
+      lpt,
+      mint_lpt,
+      vault,
+      proof,
+      self.src_s,
+      self.mint_s,
+      treasuries[0],
+      self.src_a,
+      self.mint_a,
+      treasuries[1],
+      self.src_b,
+      self.mint_b,
+      treasuries[2],
+      treasurer,
+      system,
+      self.splt,
+      sysvar_rent,
+      splata,
+    ]
+  }
+}
+
+#[test]
+fn initialize_pool_requires_the_payer_signature() {
+  let mut bench = Bench::new();
+  let owner = bench.add_user();
+  let keys = bench.initialize_pool_keys(&owner);
+  bench.signer(&keys[0], false);
+  assert_eq!(
+    bench.run(&ix(0, &[RESERVE, RESERVE, RESERVE]), &keys),
+    err(AppError::Unauthorized)
+  );
+  let pool = Pool::unpack_unchecked(&bench.account(&keys[2]).data).unwrap();
+  assert!(!pool.is_initialized());
+}
+
+#[test]
+fn initialize_pool_lets_the_payer_name_an_unsigned_owner() {
+  let mut bench = Bench::new();
+  let owner = bench.add_user();
+  bench.signer(&owner, false);
+  let keys = bench.initialize_pool_keys(&owner);
+  // Past the signatures, the zero reserve is the first check to fail
+  assert_eq!(
+    bench.run(&ix(0, &[0, RESERVE, RESERVE]), &keys),
+    err(AppError::ZeroValue)
+  );
+}
+
+///
+/// AddLiquidity
+///
+#[test]
+fn add_liquidity_mints_to_the_depositor() {
+  let mut bench = Bench::new();
+  let keys = bench.add_liquidity_keys(&bench.lpt.clone());
+  bench.run(&ix(1, &[1_000, 1_000, 1_000]), &keys).unwrap();
+  // A balanced deposit mints its share, rounded down in favor of the pool
+  let minted = bench.token(&bench.lpt.clone()).amount - RESERVE;
+  assert!(minted > 0 && minted <= 1_000);
+  assert_eq!(bench.supply(), RESERVE + minted);
+  assert_eq!(
+    bench.token(&bench.treasury_b.clone()).amount,
+    RESERVE + 1_000
+  );
+}
+
+#[test]
+fn add_liquidity_rejects_lpt_owned_by_the_treasurer() {
+  let mut bench = Bench::new();
+  let (mint_lpt, treasurer) = (bench.mint_lpt, bench.treasurer);
+  let locked_lpt = bench.add_token(&mint_lpt, &treasurer, 0);
+  let keys = bench.add_liquidity_keys(&locked_lpt);
+  assert_eq!(
+    bench.run(&ix(1, &[1_000, 1_000, 1_000]), &keys),
+    err(AppError::InvalidOwner)
+  );
+  assert_eq!(bench.supply(), RESERVE);
+}
+
+#[test]
+fn add_liquidity_ignores_aliases_of_unused_sources() {
+  let mut bench = Bench::new();
+  let mut keys = bench.add_liquidity_keys(&bench.lpt.clone());
+  // Single-sided S deposit, the A and B sources are not read
+  keys[6] = bench.src_s;
+  keys[8] = bench.treasury_b;
+  bench.run(&ix(1, &[1_000, 0, 0]), &keys).unwrap();
+  assert_eq!(
+    bench.token(&bench.treasury_s.clone()).amount,
+    RESERVE + 1_000
+  );
+  assert_eq!(bench.token(&bench.treasury_b.clone()).amount, RESERVE);
+}
+
+#[test]
+fn add_liquidity_rejects_aliased_accounts() {
+  let mut bench = Bench::new();
+  let keys = bench.add_liquidity_keys(&bench.lpt.clone());
+  let (src_s, treasury_s, treasury_a) = (bench.src_s, bench.treasury_s, bench.treasury_a);
+  // A used source that is also a treasury, twice the same source, LPT into a source
+  for (index, alias) in [(4, treasury_s), (6, src_s), (2, src_s), (5, treasury_a)].iter() {
+    let mut keys = keys.clone();
+    keys[*index] = *alias;
+    assert_eq!(
+      bench.run(&ix(1, &[1_000, 1_000, 1_000]), &keys),
+      err(AppError::InvalidInstruction)
+    );
+  }
+  assert_eq!(bench.supply(), RESERVE);
+}
+
+#[test]
+fn add_liquidity_fills_the_lpt_cap_exactly() {
+  let mut bench = Bench::new();
+  let (owner, pool) = (bench.owner, bench.pool);
+  // A balanced deposit of 1000 mints 997 LPT on this pool
+  bench
+    .run(&ix(11, &[RESERVE + 997]), &[owner, pool])
+    .unwrap();
+  let keys = bench.add_liquidity_keys(&bench.lpt.clone());
+  bench.run(&ix(1, &[1_000, 1_000, 1_000]), &keys).unwrap();
+  assert_eq!(bench.supply(), RESERVE + 997);
+}
+
+#[test]
+fn add_liquidity_rejects_minting_over_the_lpt_cap() {
+  let mut bench = Bench::new();
+  let (owner, pool) = (bench.owner, bench.pool);
+  bench
+    .run(&ix(11, &[RESERVE + 996]), &[owner, pool])
+    .unwrap();
+  let keys = bench.add_liquidity_keys(&bench.lpt.clone());
+  assert_eq!(
+    bench.run(&ix(1, &[1_000, 1_000, 1_000]), &keys),
+    err(AppError::ExceedLimit)
+  );
+  assert_eq!(bench.supply(), RESERVE);
+}
+
+#[test]
+fn add_liquidity_treats_a_zero_lpt_cap_as_none() {
+  let mut bench = Bench::new();
+  assert_eq!(bench.pool().max_lpt_supply, 0);
+  let keys = bench.add_liquidity_keys(&bench.lpt.clone());
+  bench
+    .run(&ix(1, &[RESERVE, RESERVE, RESERVE]), &keys)
+    .unwrap();
+  assert!(bench.supply() > RESERVE);
+}
+
+#[test]
+fn add_liquidity_reports_a_full_lpt_supply() {
+  let mut bench = Bench::new();
+  bench.set_supply(u64::MAX - 10);
+  let keys = bench.add_liquidity_keys(&bench.lpt.clone());
+  assert_eq!(
+    bench.run(&ix(1, &[1_000, 1_000, 1_000]), &keys),
+    err(AppError::LptSupplyFull)
+  );
+  assert_eq!(bench.supply(), u64::MAX - 10);
+  assert_eq!(bench.pool().reserve_a, RESERVE);
+}
+
+///
+/// RemoveLiquidity
+///
+#[test]
+fn remove_liquidity_rounds_payouts_down() {
+  let mut bench = Bench::new();
+  let (lpt, treasury_s, treasury_a, treasury_b) = (
+    bench.lpt,
+    bench.treasury_s,
+    bench.treasury_a,
+    bench.treasury_b,
+  );
+  bench.set_pool(|pool| {
+    pool.reserve_s = 10;
+    pool.reserve_a = 11;
+    pool.reserve_b = 13;
+  });
+  for (treasury, amount) in [(treasury_s, 10), (treasury_a, 11), (treasury_b, 13)].iter() {
+    bench.set_token(treasury, |treasury| treasury.amount = *amount);
+  }
+  bench.set_supply(7);
+  bench.set_token(&lpt, |lpt| lpt.amount = 7);
+
+  let keys = bench.remove_liquidity_keys();
+  bench.run(&ix(2, &[3]), &keys).unwrap();
+  // 3/7 of 10, 11 and 13, each rounded down
+  assert_eq!(bench.token(&bench.src_s.clone()).amount, RESERVE + 4);
+  assert_eq!(bench.token(&bench.src_a.clone()).amount, RESERVE + 4);
+  assert_eq!(bench.token(&bench.src_b.clone()).amount, RESERVE + 5);
+  let pool = bench.pool();
+  assert_eq!((pool.reserve_s, pool.reserve_a, pool.reserve_b), (6, 7, 8));
+  assert_eq!(bench.supply(), 4);
+}
+
+#[test]
+fn remove_liquidity_exits_despite_a_short_treasury() {
+  let mut bench = Bench::new();
+  let (treasury_b, src_b) = (bench.treasury_b, bench.src_b);
+  bench.set_token(&treasury_b, |treasury| treasury.amount -= 1);
+  let keys = bench.remove_liquidity_keys();
+  bench.run(&ix(2, &[RESERVE / 2]), &keys).unwrap();
+  assert_eq!(bench.token(&src_b).amount, RESERVE + RESERVE / 2);
+  assert_eq!(bench.pool().reserve_b, RESERVE / 2);
+  // Swaps still refuse to trust the drifted reserve
+  let keys = bench.swap_a_to_b_keys();
+  assert_eq!(
+    bench.run(&ix(3, &[1_000, 0]), &keys),
+    err(AppError::InsufficientFunds)
+  );
+}
+
+#[test]
+fn remove_liquidity_rejects_aliased_accounts() {
+  let mut bench = Bench::new();
+  let keys = bench.remove_liquidity_keys();
+  let (lpt, treasury_a, src_b) = (bench.lpt, bench.treasury_a, bench.src_b);
+  // Paying a treasury back into itself, two sides into one account, LPT as a destination
+  for (index, alias) in [(6, treasury_a), (6, src_b), (4, lpt)].iter() {
+    let mut keys = keys.clone();
+    keys[*index] = *alias;
+    assert_eq!(
+      bench.run(&ix(2, &[1_000]), &keys),
+      err(AppError::InvalidInstruction)
+    );
+  }
+  assert_eq!(bench.supply(), RESERVE);
+}
+
+///
+/// Version 1 pools
+///
+impl Bench {
+  // Cut the pool account back to the version 1 layout deployed pools still have
+  fn with_legacy_pool(&mut self) {
+    let pool = self.pool;
+    self.account(&pool).data.truncate(Pool::LEGACY_LEN);
+  }
+}
+
+#[test]
+fn remove_liquidity_serves_a_version_1_pool() {
+  let mut bench = Bench::new();
+  bench.with_legacy_pool();
+  let pool = bench.pool;
+  let keys = bench.remove_liquidity_keys();
+  bench.run(&ix(2, &[RESERVE / 2]), &keys).unwrap();
+  assert_eq!(bench.account(&pool).data.len(), Pool::LEGACY_LEN);
+  assert_eq!(bench.pool().reserve_a, RESERVE / 2);
+  assert_eq!(
+    bench.token(&bench.src_a.clone()).amount,
+    RESERVE + RESERVE / 2
+  );
+  assert_eq!(bench.supply(), RESERVE / 2);
+}
+
+#[test]
+fn version_1_pools_swap_and_add_liquidity() {
+  let mut bench = Bench::new();
+  bench.with_legacy_pool();
+  let keys = bench.swap_a_to_b_keys();
+  bench.run(&ix(3, &[1_000_000, 0]), &keys).unwrap();
+  assert_eq!(bench.pool().reserve_a, RESERVE + 1_000_000);
+  let keys = bench.add_liquidity_keys(&bench.lpt.clone());
+  bench.run(&ix(1, &[1_000, 1_000, 1_000]), &keys).unwrap();
+  assert!(bench.supply() > RESERVE);
+}
+
+#[test]
+fn version_1_pools_refuse_settings_they_cannot_store() {
+  let mut bench = Bench::new();
+  bench.with_legacy_pool();
+  let (owner, pool) = (bench.owner, bench.pool);
+  let before = bench.account(&pool).data.clone();
+  assert_eq!(
+    bench.run(&ix(11, &[RESERVE]), &[owner, pool]),
+    Err(ProgramError::AccountDataTooSmall)
+  );
+  assert_eq!(bench.account(&pool).data, before);
+  // Settings a version 1 pool already has still work
+  bench.run(&ix(4, &[]), &[owner, pool]).unwrap();
+  assert!(bench.pool().is_frozen());
+}
+
+///
+/// Liquidity round trip
+///
+struct Provider {
+  user: Pubkey,
+  lpt: Pubkey,
+  srcs: [Pubkey; 3],
+}
+
+impl Bench {
+  // A second liquidity provider with amount of every token and an empty LPT account
+  fn add_provider(&mut self, amount: u64) -> Provider {
+    let user = self.add_user();
+    let (mint_lpt, mint_s, mint_a, mint_b) = (self.mint_lpt, self.mint_s, self.mint_a, self.mint_b);
+    Provider {
+      user,
+      lpt: self.add_token(&mint_lpt, &user, 0),
+      srcs: [
+        self.add_token(&mint_s, &user, amount),
+        self.add_token(&mint_a, &user, amount),
+        self.add_token(&mint_b, &user, amount),
+      ],
+    }
+  }
+  // AddLiquidity and RemoveLiquidity share a layout
+  fn provider_keys(&self, provider: &Provider) -> Vec<Pubkey> {
+    let mut keys = self.remove_liquidity_keys();
+    keys[0] = provider.user;
+    keys[2] = provider.lpt;
+    keys[4] = provider.srcs[0];
+    keys[6] = provider.srcs[1];
+    keys[8] = provider.srcs[2];
+    keys
+  }
+  // Tokens held plus the share of every reserve the LPT redeems for, rounded down
+  fn provider_value(&mut self, provider: &Provider) -> [u64; 3] {
+    let (pool, supply) = (self.pool(), self.supply() as u128);
+    let lpt = self.token(&provider.lpt).amount as u128;
+    let reserves = [pool.reserve_s, pool.reserve_a, pool.reserve_b];
+    let mut value = [0; 3];
+    for i in 0..3 {
+      let share = lpt * reserves[i] as u128 / supply;
+      value[i] = self.token(&provider.srcs[i]).amount + share as u64;
+    }
+    value
+  }
+}
+
+#[test]
+fn liquidity_round_trips_within_rounding() {
+  let mut bench = Bench::new();
+  let provider = bench.add_provider(1_000_000);
+  let keys = bench.provider_keys(&provider);
+  let mut value = bench.provider_value(&provider);
+  assert_eq!(value, [1_000_000; 3]);
+  // Every step rounds in favor of the pool: the three rakes of a deposit by up to 3 units
+  // a side, a removal by up to 1
+  let mut step = |bench: &mut Bench, data: Vec<u8>, rounding: u64| {
+    bench.run(&data, &keys).unwrap();
+    let next = bench.provider_value(&provider);
+    for (next, value) in next.iter().zip(value.iter()) {
+      assert!(next <= value && value - next <= rounding, "{:?}", next);
+    }
+    value = next;
+  };
+  step(&mut bench, ix(1, &[1_000_000, 1_000_000, 1_000_000]), 3);
+  let lpt = bench.token(&provider.lpt).amount;
+  step(&mut bench, ix(2, &[lpt / 2]), 1);
+  let paid: Vec<u64> = provider
+    .srcs
+    .iter()
+    .map(|src| bench.token(src).amount)
+    .collect();
+  step(&mut bench, ix(1, &[paid[0], paid[1], paid[2]]), 3);
+  let lpt = bench.token(&provider.lpt).amount;
+  step(&mut bench, ix(2, &[lpt]), 1);
+  let held: Vec<u64> = provider
+    .srcs
+    .iter()
+    .map(|src| bench.token(src).amount)
+    .collect();
+  assert_eq!(held, value.to_vec());
+  assert!(held.iter().all(|amount| 1_000_000 - amount <= 8));
+}
+
+#[test]
+fn liquidity_keeps_the_fees_of_swaps_in_between() {
+  let mut bench = Bench::new();
+  let provider = bench.add_provider(1_000_000);
+  let keys = bench.provider_keys(&provider);
+  bench
+    .run(&ix(1, &[1_000_000, 1_000_000, 1_000_000]), &keys)
+    .unwrap();
+  let lpt = bench.token(&provider.lpt).amount;
+  bench.run(&ix(2, &[lpt / 2]), &keys).unwrap();
+  let before = bench.provider_value(&provider);
+  // A trader goes A to B and back, paying the fee twice
+  let (src_a, src_b) = (bench.src_a, bench.src_b);
+  let paid_a = bench.token(&src_a).amount;
+  let swap = bench.swap_a_to_b_keys();
+  bench.run(&ix(3, &[RESERVE / 10, 0]), &swap).unwrap();
+  let bought = bench.token(&src_b).amount - RESERVE;
+  let (treasury_a, treasury_b) = (bench.treasury_a, bench.treasury_b);
+  let swap = bench.swap_keys(&src_b, &treasury_b, &src_a, &treasury_a);
+  bench.run(&ix(3, &[bought, 0]), &swap).unwrap();
+  assert!(bench.token(&src_a).amount < paid_a);
+
+  let after = bench.provider_value(&provider);
+  assert!(after[1] > before[1] && after[2] >= before[2], "{:?}", after);
+  // The remaining position redeems for what the value promised
+  let lpt = bench.token(&provider.lpt).amount;
+  bench.run(&ix(2, &[lpt]), &keys).unwrap();
+  for (src, value) in provider.srcs.iter().zip(after.iter()) {
+    assert_eq!(bench.token(src).amount, *value);
+  }
+}
+
+///
+/// Swap
+///
+#[test]
+fn swap_accepts_quotes_up_to_the_maximum_age() {
+  let mut bench = Bench::new();
+  let keys = bench.swap_a_to_b_keys();
+  for slot in [SLOT, SLOT - MAX_QUOTE_AGE].iter() {
+    bench.run(&ix(3, &[1_000_000, 0, *slot]), &keys).unwrap();
+  }
+  assert_eq!(bench.pool().swap_count, 2);
+  assert_eq!(bench.pool().last_swap_slot, SLOT);
+}
+
+#[test]
+fn swap_rejects_stale_and_future_quotes() {
+  let mut bench = Bench::new();
+  let keys = bench.swap_a_to_b_keys();
+  assert_eq!(
+    bench.run(&ix(3, &[1_000_000, 0, SLOT - MAX_QUOTE_AGE - 1]), &keys),
+    err(AppError::Expired)
+  );
+  assert_eq!(
+    bench.run(&ix(3, &[1_000_000, 0, SLOT + 1]), &keys),
+    err(AppError::InvalidInstruction)
+  );
+  assert_eq!(bench.pool().swap_count, 0);
+}
+
+#[test]
+fn swap_pays_the_earning_only_into_the_pool_vault() {
+  let mut bench = Bench::new();
+  let (owner, pool, treasurer, mint_s) = (bench.owner, bench.pool, bench.treasurer, bench.mint_s);
+  let stranger = bench.add_user();
+  let foreign = bench.add_token(&mint_s, &stranger, 0);
+  let mut keys = bench.swap_a_to_b_keys();
+  keys[2] = foreign;
+  assert_eq!(
+    bench.run(&ix(3, &[1_000_000, 0]), &keys),
+    err(AppError::UnmatchedPool)
+  );
+  assert_eq!(bench.token(&foreign).amount, 0);
+  // Once rotated, the old vault is foreign too
+  let new_vault = bench.add_token(&mint_s, &treasurer, 0);
+  bench
+    .run(&ix(16, &[]), &[owner, pool, new_vault, treasurer])
+    .unwrap();
+  let mut keys = bench.swap_a_to_b_keys();
+  assert_eq!(
+    bench.run(&ix(3, &[1_000_000, 0]), &keys),
+    err(AppError::UnmatchedPool)
+  );
+  keys[2] = new_vault;
+  bench.run(&ix(3, &[1_000_000, 0]), &keys).unwrap();
+  assert!(bench.token(&new_vault).amount > 0);
+}
+
+#[test]
+fn swap_stops_at_the_ask_reserve_floor() {
+  let mut bench = Bench::new();
+  let (owner, pool) = (bench.owner, bench.pool);
+  // S sits at its floor already, but only the floor of the ask side applies
+  bench
+    .run(&ix(14, &[RESERVE, 0, RESERVE / 2]), &[owner, pool])
+    .unwrap();
+  let keys = bench.swap_a_to_b_keys();
+  // B goes down to about two thirds, above its floor of one half
+  bench.run(&ix(3, &[RESERVE / 2, 0]), &keys).unwrap();
+  let left = bench.pool().reserve_b;
+  assert!(left >= RESERVE / 2);
+  assert_eq!(
+    bench.run(&ix(3, &[RESERVE, 0]), &keys),
+    err(AppError::BelowMinReserve)
+  );
+  assert_eq!(bench.pool().reserve_b, left);
+}
+
+#[test]
+fn swap_rejects_a_bid_the_reserve_could_never_hold() {
+  let mut bench = Bench::new();
+  let keys = bench.swap_a_to_b_keys();
+  assert_eq!(
+    bench.run(&ix(3, &[u64::MAX - RESERVE + 1, 0]), &keys),
+    err(AppError::ExcessiveSwap)
+  );
+  assert_eq!(bench.pool().reserve_a, RESERVE);
+}
+
+#[test]
+fn swap_accepts_a_bid_that_fills_the_reserve_exactly() {
+  let mut bench = Bench::new();
+  let keys = bench.swap_a_to_b_keys();
+  // Past the overflow guard, the unreachable limit is the first check to fail
+  assert_eq!(
+    bench.run(&ix(3, &[u64::MAX - RESERVE, u64::MAX]), &keys),
+    err(AppError::ExceedLimit)
+  );
+}
+
+#[test]
+fn swap_rejects_aliased_accounts() {
+  let mut bench = Bench::new();
+  let keys = bench.swap_a_to_b_keys();
+  let (vault, src_a, treasury_b) = (bench.vault, bench.src_a, bench.treasury_b);
+  // Paid into its own source, into the vault, out of a treasury
+  for (index, alias) in [(5, src_a), (5, vault), (3, treasury_b)].iter() {
+    let mut keys = keys.clone();
+    keys[*index] = *alias;
+    assert_eq!(
+      bench.run(&ix(3, &[1_000_000, 0]), &keys),
+      err(AppError::InvalidInstruction)
+    );
+  }
+  assert_eq!(bench.pool().reserve_a, RESERVE);
+}
+
+#[test]
+fn swap_settles_the_earning_against_a_thin_s_reserve() {
+  let mut bench = Bench::new();
+  let treasury_s = bench.treasury_s;
+  bench.set_pool(|pool| pool.reserve_s = 100);
+  bench.set_token(&treasury_s, |treasury| treasury.amount = 100);
+  let keys = bench.swap_a_to_b_keys();
+  bench.run(&ix(3, &[1_000_000, 0]), &keys).unwrap();
+  // Far smaller than the earning in B, the S reserve still survives the buyback
+  let pool = bench.pool();
+  assert!(pool.reserve_s > 0 && pool.reserve_s <= 100);
+  assert_eq!(
+    bench.token(&bench.vault.clone()).amount,
+    100 - pool.reserve_s
+  );
+}
+
+#[test]
+fn swap_buys_the_earning_back_in_s() {
+  let mut bench = Bench::new();
+  let keys = bench.swap_a_to_b_keys();
+  bench.run(&ix(3, &[RESERVE / 100, 0]), &keys).unwrap();
+  let pool = bench.pool();
+  let earned = bench.token(&bench.vault.clone()).amount;
+  assert!(earned > 0);
+  assert_eq!(pool.reserve_s, RESERVE - earned);
+}
+
+#[test]
+fn swap_skips_an_earning_that_would_drain_the_s_reserve() {
+  let mut bench = Bench::new();
+  let src_a = bench.src_a;
+  bench.set_token(&src_a, |src| src.amount = 1_000 * RESERVE);
+  let keys = bench.swap_a_to_b_keys();
+  // Nearly all of B leaves, the earning is about 14% of what stays
+  bench.run(&ix(3, &[1_000 * RESERVE, 0]), &keys).unwrap();
+  let pool = bench.pool();
+  assert_eq!(pool.reserve_s, RESERVE);
+  assert_eq!(bench.token(&bench.vault.clone()).amount, 0);
+  // The earning stays in B, still backed by the treasury
+  assert_eq!(
+    bench.token(&bench.treasury_b.clone()).amount,
+    pool.reserve_b
+  );
+}
+
+#[test]
+fn swap_keeps_reserves_backed_through_rounding() {
+  let mut bench = Bench::new();
+  let (treasury_s, treasury_a, treasury_b) = (bench.treasury_s, bench.treasury_a, bench.treasury_b);
+  // Odd reserves and amounts hit every rounding branch of the curve, fee and earning
+  bench.set_pool(|pool| {
+    pool.reserve_a = 1_000_003;
+    pool.reserve_b = 999_983;
+  });
+  bench.set_token(&treasury_a, |treasury| treasury.amount = 1_000_003);
+  bench.set_token(&treasury_b, |treasury| treasury.amount = 999_983);
+  let a_to_b = bench.swap_a_to_b_keys();
+  let b_to_a = bench.swap_keys(&bench.src_b, &treasury_b, &bench.src_a, &treasury_a);
+  for (i, amount) in [997u64, 13, 4_001, 77_777, 1_009, 31].iter().enumerate() {
+    let keys = if i % 2 == 0 { &a_to_b } else { &b_to_a };
+    bench.run(&ix(3, &[*amount, 0]), keys).unwrap();
+    let pool = bench.pool();
+    assert_eq!(bench.token(&treasury_a).amount, pool.reserve_a);
+    assert_eq!(bench.token(&treasury_b).amount, pool.reserve_b);
+    assert_eq!(bench.token(&treasury_s).amount, pool.reserve_s);
+  }
+  assert_eq!(bench.pool().swap_count, 6);
+}
+
+#[test]
+fn swap_rejects_an_ask_reserve_the_treasury_no_longer_backs() {
+  let mut bench = Bench::new();
+  let mint_b = bench.mint_b;
+  TAXED_MINTS.lock().unwrap().push(mint_b);
+  let keys = bench.swap_a_to_b_keys();
+  // Paying out of B costs the treasury one unit more than the pool records
+  assert_eq!(
+    bench.run(&ix(3, &[1_000_000, 0]), &keys),
+    err(AppError::InvariantViolation)
+  );
+  assert_eq!(bench.pool().reserve_b, RESERVE);
+}
+
+#[test]
+fn swap_rejects_an_empty_s_reserve_when_earning() {
+  let mut bench = Bench::new();
+  bench.set_pool(|pool| pool.reserve_s = 0);
+  let keys = bench.swap_a_to_b_keys();
+  assert_eq!(
+    bench.run(&ix(3, &[1_000_000, 0]), &keys),
+    err(AppError::InsufficientFunds)
+  );
+}
+
+// Seeded xorshift64, so a failing case reproduces
+struct Corpus(u64);
+
+impl Corpus {
+  fn next(&mut self) -> u64 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    self.0
+  }
+  // Spread over every magnitude up to 10^digits
+  fn amount(&mut self, digits: u64) -> u64 {
+    let scale = 10u64.pow((self.next() % (digits + 1)) as u32);
+    1 + self.next() % scale
+  }
+}
+
+#[test]
+fn swap_matches_the_reference_swap() {
+  let mut corpus = Corpus(0x5eed_d1ff);
+  let (mut settled, mut earned) = (0, 0);
+  for case in 0..500 {
+    let mut bench = Bench::new();
+    let reserves = [corpus.amount(12), corpus.amount(12), corpus.amount(12)];
+    let bid_code = (corpus.next() % 3) as usize;
+    let ask_code = (bid_code + 1 + (corpus.next() % 2) as usize) % 3;
+    let amount = corpus.amount(13);
+    let srcs = [bench.src_s, bench.src_a, bench.src_b];
+    let treasuries = [bench.treasury_s, bench.treasury_a, bench.treasury_b];
+    bench.set_pool(|pool| {
+      pool.reserve_s = reserves[0];
+      pool.reserve_a = reserves[1];
+      pool.reserve_b = reserves[2];
+    });
+    for (treasury, reserve) in treasuries.iter().zip(reserves.iter()) {
+      bench.set_token(treasury, |treasury| treasury.amount = *reserve);
+    }
+    bench.set_token(&srcs[bid_code], |src| src.amount = amount);
+    let (dst, vault) = (srcs[ask_code], bench.vault);
+    let dst_before = bench.token(&dst).amount;
+
+    let expected = Oracle::reference_swap(reserves, bid_code, ask_code, amount);
+    let keys = bench.swap_keys(
+      &srcs[bid_code],
+      &treasuries[bid_code],
+      &dst,
+      &treasuries[ask_code],
+    );
+    let actual = bench.run(&ix(3, &[amount, 0]), &keys);
+    let context = (case, reserves, bid_code, ask_code, amount);
+    let expected = match expected {
+      Ok(expected) => expected,
+      Err(error) => {
+        assert_eq!(actual, err(error), "{:?}", context);
+        continue;
+      }
+    };
+    assert_eq!(actual, Ok(()), "{:?}", context);
+    let pool = bench.pool();
+    assert_eq!(
+      [pool.reserve_s, pool.reserve_a, pool.reserve_b],
+      expected.reserves,
+      "{:?}",
+      context
+    );
+    assert_eq!(
+      bench.token(&dst).amount - dst_before,
+      expected.paid_amount,
+      "{:?}",
+      context
+    );
+    assert_eq!(
+      bench.token(&vault).amount,
+      expected.earning_in_sen,
+      "{:?}",
+      context
+    );
+    settled += 1;
+    if expected.earning_in_sen > 0 {
+      earned += 1;
+    }
+  }
+  // The corpus reaches both the plain and the earning paths
+  assert!(settled > 100 && earned > 50, "{} {}", settled, earned);
+}
+
+///
+/// Earning accrual
+///
+impl Bench {
+  fn sweep_earning_keys(&self) -> Vec<Pubkey> {
+    vec![
+      self.pool,
+      self.vault,
+      self.treasury_s,
+      self.treasurer,
+      self.splt,
+    ]
+  }
+}
+
+#[test]
+fn sweep_earning_pays_what_swaps_accrued() {
+  let mut bench = Bench::new();
+  let (owner, pool, vault, treasury_s) = (bench.owner, bench.pool, bench.vault, bench.treasury_s);
+  bench.run(&[9, 1], &[owner, pool]).unwrap();
+  let keys = bench.swap_a_to_b_keys();
+  let mut pending = 0;
+  for _ in 0..3 {
+    bench.run(&ix(3, &[1_000_000_000, 0]), &keys).unwrap();
+    // Accrued earning stays in the S treasury on top of the reserve
+    let pool_data = bench.pool();
+    assert!(pool_data.pending_earning > pending);
+    pending = pool_data.pending_earning;
+    assert_eq!(
+      bench.token(&treasury_s).amount,
+      pool_data.reserve_s + pending
+    );
+    assert_eq!(bench.token(&vault).amount, 0);
+  }
+
+  let keys = bench.sweep_earning_keys();
+  bench.run(&ix(10, &[]), &keys).unwrap();
+  assert_eq!(bench.token(&vault).amount, pending);
+  assert_eq!(bench.pool().pending_earning, 0);
+  assert_eq!(bench.token(&treasury_s).amount, bench.pool().reserve_s);
+  assert_eq!(bench.run(&ix(10, &[]), &keys), err(AppError::ZeroValue));
+}
+
+#[test]
+fn set_earning_mode_keeps_what_is_already_pending() {
+  let mut bench = Bench::new();
+  let (owner, pool, vault) = (bench.owner, bench.pool, bench.vault);
+  bench.run(&[9, 1], &[owner, pool]).unwrap();
+  let keys = bench.swap_a_to_b_keys();
+  bench.run(&ix(3, &[1_000_000_000, 0]), &keys).unwrap();
+  let pending = bench.pool().pending_earning;
+  assert!(pending > 0);
+
+  // Back to paying out: the next earning goes straight to the vault
+  bench.run(&[9, 0], &[owner, pool]).unwrap();
+  assert_eq!(bench.pool().pending_earning, pending);
+  bench.run(&ix(3, &[1_000_000_000, 0]), &keys).unwrap();
+  let paid = bench.token(&vault).amount;
+  assert!(paid > 0);
+  assert_eq!(bench.pool().pending_earning, pending);
+  // The earlier accrual is still sweepable
+  let keys = bench.sweep_earning_keys();
+  bench.run(&ix(10, &[]), &keys).unwrap();
+  assert_eq!(bench.token(&vault).amount, paid + pending);
+  assert_eq!(bench.pool().pending_earning, 0);
+}
+
+///
+/// SwapFromSol
+///
+impl Bench {
+  // Turn A into wrapped SOL and add what SwapFromSol needs on top of the pool
+  fn with_native_a(&mut self) -> (Pubkey, Vec<Pubkey>) {
+    let mint = Mint {
+      mint_authority: COption::None,
+      decimals: 9,
+      is_initialized: true,
+      ..Mint::default()
+    };
+    let mut data = vec![0; Mint::LEN];
+    Mint::pack(mint, &mut data).unwrap();
+    let native_mint = self.add(native_mint_id(), self.splt, 1_000_000, data);
+    let treasurer = self.treasurer;
+    let treasury_a = self.add_token(&native_mint, &treasurer, RESERVE);
+    self.set_token(&treasury_a, |treasury| {
+      treasury.is_native = COption::Some(Rent::default().minimum_balance(Account::LEN))
+    });
+    self.account(&treasury_a).lamports += RESERVE;
+    self.set_pool(|pool| {
+      pool.mint_a = native_mint;
+      pool.treasury_a = treasury_a;
+    });
+    self.treasury_a = treasury_a;
+    // The runtime cannot reassign owners here, so the fresh account is born to SPL token
+    let wsol = self.add(Pubkey::new_unique(), self.splt, 0, vec![0; Account::LEN]);
+    self.signer(&wsol, true);
+    self.account(&self.owner.clone()).lamports = 2 * RESERVE;
+    let system = self.add(system_program::id(), Pubkey::default(), 1, vec![]);
+    let rent = Rent::default();
+    let mut rent_data = rent.lamports_per_byte_year.to_le_bytes().to_vec();
+    rent_data.extend_from_slice(&rent.exemption_threshold.to_le_bytes());
+    rent_data.push(rent.burn_percent);
+    let sysvar_rent = self.add(sysvar::rent::id(), Pubkey::default(), 1, rent_data);
+    let keys = vec![
+      self.owner,
+      self.pool,
+      self.vault,
+      wsol,
+      native_mint,
+      treasury_a,
+      self.src_b,
+      self.treasury_b,
+      self.treasury_s,
+      self.treasurer,
+      system,
+      self.splt,
+      sysvar_rent,
+    ];
+    (wsol, keys)
+  }
+}
+
+#[test]
+fn swap_from_sol_wraps_swaps_and_unwraps() {
+  let mut bench = Bench::new();
+  let (wsol, keys) = bench.with_native_a();
+  let owner = bench.owner;
+  let lamports = bench.lamports(&owner);
+  let amount = 1_000_000;
+
+  bench.run(&ix(12, &[amount, 1]), &keys).unwrap();
+  let pool = bench.pool();
+  assert_eq!(pool.reserve_a, RESERVE + amount);
+  assert!(pool.reserve_b < RESERVE);
+  assert!(bench.token(&bench.src_b.clone()).amount > RESERVE);
+  // Only the swapped lamports left, the temporary account's rent came back
+  assert_eq!(bench.lamports(&owner), lamports - amount);
+  assert_eq!(bench.lamports(&wsol), 0);
+}
+
+#[test]
+fn swap_from_sol_checks_accounts_before_wrapping() {
+  let mut bench = Bench::new();
+  let (wsol, keys) = bench.with_native_a();
+  let fake_splt = bench.add(Pubkey::new_unique(), Pubkey::default(), 1, vec![]);
+  let foreign_pool = bench.add(
+    Pubkey::new_unique(),
+    Pubkey::new_unique(),
+    1,
+    vec![0; Pool::LEN],
+  );
+  for (index, key, error) in [
+    (11, fake_splt, AppError::IncorrectProgramId),
+    (1, foreign_pool, AppError::IncorrectProgramId),
+  ]
+  .iter()
+  {
+    let mut keys = keys.clone();
+    keys[*index] = *key;
+    assert_eq!(
+      bench.run(&ix(12, &[1_000_000, 0]), &keys),
+      err(error.clone())
+    );
+    // Nothing was created
+    assert_eq!(bench.lamports(&wsol), 0);
+  }
+}
+
+///
+/// ReconcileReserve
+///
+#[test]
+fn reconcile_reserve_takes_in_a_donation() {
+  let mut bench = Bench::new();
+  let (treasury_s, treasury_a) = (bench.treasury_s, bench.treasury_a);
+  bench.set_token(&treasury_a, |treasury| treasury.amount += 500);
+  // Accrued earning sits in the S treasury but is not reserve
+  bench.set_pool(|pool| pool.pending_earning = 40);
+  bench.set_token(&treasury_s, |treasury| treasury.amount += 40);
+  let keys = bench.reconcile_reserve_keys();
+  bench.run(&ix(13, &[]), &keys).unwrap();
+  let pool = bench.pool();
+  assert_eq!(pool.reserve_a, RESERVE + 500);
+  assert_eq!(pool.reserve_s, RESERVE);
+  assert_eq!(pool.reserve_b, RESERVE);
+}
+
+#[test]
+fn reconcile_reserve_writes_a_short_balance_down() {
+  let mut bench = Bench::new();
+  let treasury_b = bench.treasury_b;
+  bench.set_token(&treasury_b, |treasury| treasury.amount -= 500);
+  // The drifted pool cannot swap out of B until it is repaired
+  let keys = bench.swap_a_to_b_keys();
+  assert_eq!(
+    bench.run(&ix(3, &[1_000, 0]), &keys),
+    err(AppError::InsufficientFunds)
+  );
+  let keys = bench.reconcile_reserve_keys();
+  bench.run(&ix(13, &[]), &keys).unwrap();
+  assert_eq!(bench.pool().reserve_b, RESERVE - 500);
+  let keys = bench.swap_a_to_b_keys();
+  bench.run(&ix(3, &[1_000, 0]), &keys).unwrap();
+}
+
+#[test]
+fn reconcile_reserve_rejects_unusable_treasuries() {
+  let mut bench = Bench::new();
+  let (treasury_a, mint_b) = (bench.treasury_a, bench.mint_b);
+  let keys = bench.reconcile_reserve_keys();
+  bench.set_token(&treasury_a, |treasury| treasury.mint = mint_b);
+  assert_eq!(bench.run(&ix(13, &[]), &keys), err(AppError::InvalidMint));
+  let mint_a = bench.mint_a;
+  bench.set_token(&treasury_a, |treasury| {
+    treasury.mint = mint_a;
+    treasury.state = AccountState::Frozen;
+  });
+  assert_eq!(bench.run(&ix(13, &[]), &keys), err(AppError::Frozen));
+}
+
+#[test]
+fn reconcile_reserve_accepts_the_treasury_authority() {
+  let mut bench = Bench::new();
+  let authority = bench.add_user();
+  let treasury_a = bench.treasury_a;
+  bench.set_token(&treasury_a, |treasury| treasury.amount += 500);
+  bench.set_pool(|pool| pool.treasury_authority = authority);
+  let mut keys = bench.reconcile_reserve_keys();
+  keys[0] = authority;
+  bench.run(&ix(13, &[]), &keys).unwrap();
+  assert_eq!(bench.pool().reserve_a, RESERVE + 500);
+}
+
+#[test]
+fn reconcile_reserve_rejects_other_signers() {
+  let mut bench = Bench::new();
+  let (authority, stranger) = (bench.add_user(), bench.add_user());
+  let mut keys = bench.reconcile_reserve_keys();
+  // Unset, the authority is nobody
+  keys[0] = authority;
+  assert_eq!(bench.run(&ix(13, &[]), &keys), err(AppError::InvalidOwner));
+  bench.set_pool(|pool| pool.treasury_authority = authority);
+  keys[0] = stranger;
+  assert_eq!(bench.run(&ix(13, &[]), &keys), err(AppError::InvalidOwner));
+  keys[0] = authority;
+  bench.signer(&authority, false);
+  assert_eq!(bench.run(&ix(13, &[]), &keys), err(AppError::Unauthorized));
+}
+
+///
+/// Owner instructions
+///
+impl Bench {
+  // Every owner-gated instruction, with arguments the owner can run
+  fn owner_arms(&mut self) -> Vec<(&'static str, Vec<u8>, Vec<Pubkey>)> {
+    let (owner, pool, treasurer, splt) = (self.owner, self.pool, self.treasurer, self.splt);
+    let (mint_s, vault, src_s) = (self.mint_s, self.vault, self.src_s);
+    self.set_token(&vault, |vault| vault.amount = 100);
+    let new_owner = self.add_user();
+    let new_vault = self.add_token(&mint_s, &treasurer, 0);
+    vec![
+      ("FreezePool", ix(4, &[]), vec![owner, pool]),
+      ("ThawPool", ix(5, &[]), vec![owner, pool]),
+      (
+        "Earn",
+        ix(6, &[100]),
+        vec![owner, pool, vault, src_s, treasurer, splt],
+      ),
+      (
+        "TransferPoolOwnership",
+        ix(7, &[]),
+        vec![owner, pool, new_owner],
+      ),
+      ("SetEarningMode", vec![9, 1], vec![owner, pool]),
+      ("SetMaxLptSupply", ix(11, &[RESERVE]), vec![owner, pool]),
+      ("SetMinReserve", ix(14, &[1, 1, 1]), vec![owner, pool]),
+      (
+        "SetTreasuryAuthority",
+        ix(15, &[]),
+        vec![owner, pool, new_owner],
+      ),
+      (
+        "SetVault",
+        ix(16, &[]),
+        vec![owner, pool, new_vault, treasurer],
+      ),
+    ]
+  }
+}
+
+#[test]
+fn owner_instructions_accept_the_owner() {
+  let arms = Bench::new().owner_arms().len();
+  for i in 0..arms {
+    let mut bench = Bench::new();
+    let (name, data, keys) = bench.owner_arms().swap_remove(i);
+    assert_eq!(bench.run(&data, &keys), Ok(()), "{}", name);
+  }
+}
+
+#[test]
+fn owner_instructions_reject_an_unsigned_owner() {
+  let arms = Bench::new().owner_arms().len();
+  for i in 0..arms {
+    let mut bench = Bench::new();
+    let (name, data, keys) = bench.owner_arms().swap_remove(i);
+    let (owner, before) = (bench.owner, bench.pool());
+    bench.signer(&owner, false);
+    assert_eq!(
+      bench.run(&data, &keys),
+      err(AppError::Unauthorized),
+      "{}",
+      name
+    );
+    assert_eq!(bench.pool(), before, "{}", name);
+  }
+}
+
+#[test]
+fn owner_instructions_reject_a_signer_other_than_the_owner() {
+  let arms = Bench::new().owner_arms().len();
+  for i in 0..arms {
+    let mut bench = Bench::new();
+    let (name, data, mut keys) = bench.owner_arms().swap_remove(i);
+    let before = bench.pool();
+    keys[0] = bench.add_user();
+    assert_eq!(
+      bench.run(&data, &keys),
+      err(AppError::InvalidOwner),
+      "{}",
+      name
+    );
+    assert_eq!(bench.pool(), before, "{}", name);
+  }
+}
+
+#[test]
+fn owner_instructions_reject_the_treasury_authority() {
+  let arms = Bench::new().owner_arms().len();
+  for i in 0..arms {
+    let mut bench = Bench::new();
+    let (name, data, mut keys) = bench.owner_arms().swap_remove(i);
+    let authority = bench.add_user();
+    bench.set_pool(|pool| pool.treasury_authority = authority);
+    keys[0] = authority;
+    assert_eq!(
+      bench.run(&data, &keys),
+      err(AppError::InvalidOwner),
+      "{}",
+      name
+    );
+  }
+}
+
+///
+/// SetVault
+///
+#[test]
+fn set_vault_rotates_to_a_treasurer_s_account() {
+  let mut bench = Bench::new();
+  let (owner, pool, treasurer, mint_s) = (bench.owner, bench.pool, bench.treasurer, bench.mint_s);
+  let new_vault = bench.add_token(&mint_s, &treasurer, 0);
+  bench
+    .run(&ix(16, &[]), &[owner, pool, new_vault, treasurer])
+    .unwrap();
+  assert_eq!(bench.pool().vault, new_vault);
+}
+
+#[test]
+fn set_vault_rejects_unusable_vaults() {
+  let mut bench = Bench::new();
+  let (owner, pool, treasurer) = (bench.owner, bench.pool, bench.treasurer);
+  let (mint_s, mint_a, vault) = (bench.mint_s, bench.mint_a, bench.vault);
+  let stranger = bench.add_user();
+  let wrong_mint = bench.add_token(&mint_a, &treasurer, 0);
+  let wrong_owner = bench.add_token(&mint_s, &stranger, 0);
+  // SPL account bytes under another program
+  let data = bench.account(&vault).data.clone();
+  let forged = bench.add(Pubkey::new_unique(), stranger, 1_000_000, data);
+  let unset = bench.add(Pubkey::default(), system_program::id(), 0, vec![]);
+  for (new_vault, error) in [
+    (wrong_mint, AppError::InvalidMint),
+    (wrong_owner, AppError::InvalidOwner),
+    (forged, AppError::IncorrectProgramId),
+    (unset, AppError::InvalidInstruction),
+  ]
+  .iter()
+  {
+    assert_eq!(
+      bench.run(&ix(16, &[]), &[owner, pool, *new_vault, treasurer]),
+      err(error.clone())
+    );
+  }
+  assert_eq!(bench.pool().vault, vault);
+}
+
+#[test]
+fn set_vault_rejects_the_pool_treasuries() {
+  let mut bench = Bench::new();
+  let (owner, pool, treasurer, vault) = (bench.owner, bench.pool, bench.treasurer, bench.vault);
+  let (treasury_s, treasury_a, treasury_b) = (bench.treasury_s, bench.treasury_a, bench.treasury_b);
+  for treasury in [treasury_s, treasury_a, treasury_b].iter() {
+    assert_eq!(
+      bench.run(&ix(16, &[]), &[owner, pool, *treasury, treasurer]),
+      err(AppError::InvalidInstruction)
+    );
+  }
+  assert_eq!(bench.pool().vault, vault);
+  // Earn can only pay out of the vault, never the S reserve
+  let src_s = bench.src_s;
+  let keys = [owner, pool, treasury_s, src_s, treasurer, bench.splt];
+  assert_eq!(
+    bench.run(&ix(6, &[RESERVE]), &keys),
+    err(AppError::InvalidOwner)
+  );
+  assert_eq!(bench.token(&treasury_s).amount, RESERVE);
+}
+
+///
+/// TransferAndClose
+///
+#[test]
+fn transfer_and_close_moves_the_full_position() {
+  let mut bench = Bench::new();
+  let (owner, mint_lpt) = (bench.owner, bench.mint_lpt);
+  let dst_lpt = bench.add_token(&mint_lpt, &owner, 5);
+  let (src_lpt, rent) = (bench.lpt, bench.lamports(&bench.lpt.clone()));
+  let owner_lamports = bench.lamports(&owner);
+
+  let keys = bench.transfer_and_close_keys(&src_lpt, &dst_lpt);
+  bench.run(&ix(8, &[]), &keys).unwrap();
+  assert_eq!(bench.token(&dst_lpt).amount, RESERVE + 5);
+  assert_eq!(bench.lamports(&src_lpt), 0);
+  assert_eq!(bench.lamports(&owner), owner_lamports + rent);
+  assert_eq!(bench.supply(), RESERVE);
+}
+
+#[test]
+fn transfer_and_close_rejects_its_own_source() {
+  let mut bench = Bench::new();
+  let lpt = bench.lpt;
+  let keys = bench.transfer_and_close_keys(&lpt, &lpt);
+  assert_eq!(bench.run(&ix(8, &[]), &keys), err(AppError::SamedLpt));
+  assert_eq!(bench.token(&lpt).amount, RESERVE);
+}
+
+#[test]
+fn transfer_and_close_requires_the_position_owner() {
+  let mut bench = Bench::new();
+  let mint_lpt = bench.mint_lpt;
+  let stranger = bench.add_user();
+  let dst_lpt = bench.add_token(&mint_lpt, &stranger, 0);
+  let mut keys = bench.transfer_and_close_keys(&bench.lpt.clone(), &dst_lpt);
+  keys[0] = stranger;
+  assert_eq!(bench.run(&ix(8, &[]), &keys), err(AppError::InvalidOwner));
+}
+
+#[test]
+fn transfer_and_close_rejects_a_frozen_account() {
+  let mut bench = Bench::new();
+  let (owner, mint_lpt, src_lpt) = (bench.owner, bench.mint_lpt, bench.lpt);
+  let dst_lpt = bench.add_token(&mint_lpt, &owner, 0);
+  let keys = bench.transfer_and_close_keys(&src_lpt, &dst_lpt);
+  for frozen in [dst_lpt, src_lpt].iter() {
+    bench.set_token(frozen, |lpt| lpt.state = AccountState::Frozen);
+    assert_eq!(bench.run(&ix(8, &[]), &keys), err(AppError::Frozen));
+    bench.set_token(frozen, |lpt| lpt.state = AccountState::Initialized);
+  }
+  assert_eq!(bench.token(&src_lpt).amount, RESERVE);
+  assert_eq!(bench.token(&dst_lpt).amount, 0);
+}
+
+#[test]
+fn transfer_and_close_rejects_a_source_with_an_active_delegate() {
+  let mut bench = Bench::new();
+  let (owner, mint_lpt, src_lpt) = (bench.owner, bench.mint_lpt, bench.lpt);
+  let dst_lpt = bench.add_token(&mint_lpt, &owner, 0);
+  let delegate = bench.add_user();
+  bench.set_token(&src_lpt, |lpt| {
+    lpt.delegate = COption::Some(delegate);
+    lpt.delegated_amount = 1;
+  });
+  let keys = bench.transfer_and_close_keys(&src_lpt, &dst_lpt);
+  assert_eq!(
+    bench.run(&ix(8, &[]), &keys),
+    err(AppError::DelegateStillActive)
+  );
+  assert_eq!(bench.token(&src_lpt).amount, RESERVE);
+  // A spent allowance no longer holds the position
+  bench.set_token(&src_lpt, |lpt| lpt.delegated_amount = 0);
+  bench.run(&ix(8, &[]), &keys).unwrap();
+  assert_eq!(bench.token(&dst_lpt).amount, RESERVE);
+}
@@ -45,6 +45,10 @@ impl Account {
   pub fn is_native(&self) -> bool {
     self.is_native.is_some()
   }
+  /// Checks if account is empty and has no active delegate
+  pub fn is_closable(&self) -> bool {
+    self.amount == 0 && self.delegate.is_none()
+  }
 }
 
 ///
@@ -177,3 +181,61 @@ fn unpack_coption_u64(src: &[u8; 12]) -> Result<COption<u64>, ProgramError> {
     _ => Err(ProgramError::InvalidAccountData),
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::schema::golden::decode;
+
+  // A fully populated SPL token account, as laid out by the token program
+  const ACCOUNT: &str = concat!(
+    "0101010101010101010101010101010101010101010101010101010101010101", // mint
+    "0202020202020202020202020202020202020202020202020202020202020202", // owner
+    "40420f0000000000",                                                 // amount
+    "01000000",                                                         // delegate tag
+    "0303030303030303030303030303030303030303030303030303030303030303", // delegate
+    "02",                                                               // state
+    "01000000",                                                         // is_native tag
+    "f01d1f0000000000",                                                 // is_native
+    "f401000000000000",                                                 // delegated_amount
+    "01000000",                                                         // close_authority tag
+    "0404040404040404040404040404040404040404040404040404040404040404", // close_authority
+  );
+
+  #[test]
+  fn layout_matches_the_golden_fixture() {
+    let key = |byte| Pubkey::new_from_array([byte; 32]);
+    let account = Account {
+      mint: key(1),
+      owner: key(2),
+      amount: 1_000_000,
+      delegate: COption::Some(key(3)),
+      state: AccountState::Frozen,
+      is_native: COption::Some(2_039_280),
+      delegated_amount: 500,
+      close_authority: COption::Some(key(4)),
+    };
+    let golden = decode(ACCOUNT);
+    let mut packed = vec![0; Account::LEN];
+    Account::pack(account, &mut packed).unwrap();
+    assert_eq!(packed, golden);
+    assert_eq!(Account::unpack(&golden).unwrap(), account);
+  }
+
+  #[test]
+  fn is_closable_requires_an_empty_undelegated_account() {
+    let empty = Account {
+      state: AccountState::Initialized,
+      ..Account::default()
+    };
+    assert!(empty.is_closable());
+    let funded = Account { amount: 1, ..empty };
+    assert!(!funded.is_closable());
+    // A delegate blocks closing even once its allowance is spent
+    let delegated = Account {
+      delegate: COption::Some(Pubkey::new_from_array([3; 32])),
+      ..empty
+    };
+    assert!(!delegated.is_closable());
+  }
+}
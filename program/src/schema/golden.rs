@@ -0,0 +1,9 @@
+///
+/// Bytes of a hex golden fixture, two digits per byte
+///
+pub fn decode(hex: &str) -> Vec<u8> {
+  (0..hex.len())
+    .step_by(2)
+    .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+    .collect()
+}
@@ -107,3 +107,36 @@ fn unpack_coption_key(src: &[u8; 36]) -> Result<COption<Pubkey>, ProgramError> {
     _ => Err(ProgramError::InvalidAccountData),
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::schema::golden::decode;
+
+  // An LPT mint, as laid out by the token program
+  const MINT: &str = concat!(
+    "01000000",                                                         // mint_authority tag
+    "0101010101010101010101010101010101010101010101010101010101010101", // mint_authority
+    "40420f0000000000",                                                 // supply
+    "09",                                                               // decimals
+    "01",                                                               // is_initialized
+    "00000000",                                                         // freeze_authority tag
+    "0000000000000000000000000000000000000000000000000000000000000000", // freeze_authority
+  );
+
+  #[test]
+  fn layout_matches_the_golden_fixture() {
+    let mint = Mint {
+      mint_authority: COption::Some(Pubkey::new_from_array([1; 32])),
+      supply: 1_000_000,
+      decimals: 9,
+      is_initialized: true,
+      freeze_authority: COption::None,
+    };
+    let golden = decode(MINT);
+    let mut packed = vec![0; Mint::LEN];
+    Mint::pack(mint, &mut packed).unwrap();
+    assert_eq!(packed, golden);
+    assert_eq!(Mint::unpack(&golden).unwrap(), mint);
+  }
+}
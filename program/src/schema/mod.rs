@@ -1,3 +1,5 @@
 pub mod account;
+#[cfg(test)]
+mod golden;
 pub mod mint;
 pub mod pool;
@@ -7,6 +7,9 @@ use solana_program::{
   pubkey::Pubkey,
 };
 
+// Layout version written after the legacy fields, version 1 had no version byte
+pub const POOL_VERSION: u8 = 2;
+
 ///
 /// Pool state
 ///
@@ -44,57 +47,38 @@ pub struct Pool {
   pub mint_b: Pubkey,
   pub treasury_b: Pubkey,
   pub reserve_b: u64,
-}
 
-///
-/// Pool implementation
-///
-impl Pool {
-  // Is frozen
-  pub fn is_frozen(&self) -> bool {
-    self.state == PoolState::Frozen
-  }
-  // Verify the pair of mint and treasury
-  // 0: None, 1: S pool, 2: A pool, 3: B pool
-  pub fn get_reserve(&self, treasury: &Pubkey) -> Option<(u8, u64)> {
-    if self.treasury_s == *treasury {
-      return Some((0, self.reserve_s));
-    }
-    if self.treasury_a == *treasury {
-      return Some((1, self.reserve_a));
-    }
-    if self.treasury_b == *treasury {
-      return Some((2, self.reserve_b));
-    }
+  pub accrue_earning: bool,
+  pub pending_earning: u64,
+  pub max_lpt_supply: u64,
 
-    None
-  }
+  pub swap_count: u64,
+  pub last_swap_slot: u64,
+
+  // Swaps cannot take an ask reserve below its floor, in units of its own mint
+  pub min_reserve_s: u64,
+  pub min_reserve_a: u64,
+  pub min_reserve_b: u64,
+  // May sign ReconcileReserve besides the owner, the default key means unset
+  pub treasury_authority: Pubkey,
 }
 
 ///
-/// Sealed trait
+/// Pool implementation
 ///
-impl Sealed for Pool {}
+impl Pool {
+  // Length of the version 1 layout, owner to reserve_b
+  pub const LEGACY_LEN: usize = 32 + 1 + 32 + 32 + 3 * (32 + 32 + 8);
 
-///
-/// IsInitialized trait
-///
-impl IsInitialized for Pool {
-  fn is_initialized(&self) -> bool {
-    self.state != PoolState::Uninitialized
+  // Read a version 1 account, the fields it lacks take their defaults
+  pub fn from_legacy(src: &[u8]) -> Result<Self, ProgramError> {
+    if src.len() != Self::LEGACY_LEN {
+      return Err(ProgramError::InvalidAccountData);
+    }
+    Self::unpack_legacy(array_ref![src, 0, Pool::LEGACY_LEN])
   }
-}
-
-///
-/// Pack trait
-///
-impl Pack for Pool {
-  // Fixed length
-  const LEN: usize = 32 + 1 + 32 + 32 + 3 * (32 + 32 + 8);
-  // Unpack data from [u8] to the data struct
-  fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
-    msg!("Read pool data");
-    let src = array_ref![src, 0, 313];
+  // The version 1 fields, shared by both layouts as a prefix
+  fn unpack_legacy(src: &[u8; Pool::LEGACY_LEN]) -> Result<Self, ProgramError> {
     let (
       owner,
       state,
@@ -124,12 +108,11 @@ impl Pack for Pool {
       mint_b: Pubkey::new_from_array(*mint_b),
       treasury_b: Pubkey::new_from_array(*treasury_b),
       reserve_b: u64::from_le_bytes(*reserve_b),
+      ..Pool::default()
     })
   }
-  // Pack data from the data struct to [u8]
-  fn pack_into_slice(&self, dst: &mut [u8]) {
-    msg!("Write pool data");
-    let dst = array_mut_ref![dst, 0, 313];
+  // Write the version 1 prefix
+  fn pack_legacy(&self, dst: &mut [u8; Pool::LEGACY_LEN]) {
     let (
       dst_owner,
       dst_state,
@@ -159,6 +142,7 @@ impl Pack for Pool {
       ref mint_b,
       ref treasury_b,
       reserve_b,
+      ..
     } = self;
     dst_owner.copy_from_slice(owner.as_ref());
     *dst_state = [state as u8];
@@ -174,4 +158,370 @@ impl Pack for Pool {
     dst_treasury_b.copy_from_slice(treasury_b.as_ref());
     *dst_reserve_b = reserve_b.to_le_bytes();
   }
+  // Read either layout, a version 1 account comes back with the later fields defaulted
+  pub fn unpack_versioned(src: &[u8]) -> Result<Self, ProgramError> {
+    if src.len() != Self::LEGACY_LEN {
+      return Self::unpack(src);
+    }
+    let pool = Self::from_legacy(src)?;
+    if !pool.is_initialized() {
+      return Err(ProgramError::UninitializedAccount);
+    }
+    Ok(pool)
+  }
+  // Write back in the layout the account already has. A version 1 account cannot grow, so
+  // it drops the activity counters and refuses any setting it has no room for
+  pub fn pack_versioned(pool: Self, dst: &mut [u8]) -> Result<(), ProgramError> {
+    if dst.len() != Self::LEGACY_LEN {
+      return Self::pack(pool, dst);
+    }
+    if !pool.fits_legacy() {
+      return Err(ProgramError::AccountDataTooSmall);
+    }
+    pool.pack_legacy(array_mut_ref![dst, 0, Pool::LEGACY_LEN]);
+    Ok(())
+  }
+  // Every setting added after version 1 is still at its default
+  fn fits_legacy(&self) -> bool {
+    !self.accrue_earning
+      && self.pending_earning == 0
+      && self.max_lpt_supply == 0
+      && self.min_reserve_s == 0
+      && self.min_reserve_a == 0
+      && self.min_reserve_b == 0
+      && self.treasury_authority == Pubkey::default()
+  }
+  // Is frozen
+  pub fn is_frozen(&self) -> bool {
+    self.state == PoolState::Frozen
+  }
+  // Verify the pair of mint and treasury
+  // 0: None, 1: S pool, 2: A pool, 3: B pool
+  pub fn get_reserve(&self, treasury: &Pubkey) -> Option<(u8, u64)> {
+    if self.treasury_s == *treasury {
+      return Some((0, self.reserve_s));
+    }
+    if self.treasury_a == *treasury {
+      return Some((1, self.reserve_a));
+    }
+    if self.treasury_b == *treasury {
+      return Some((2, self.reserve_b));
+    }
+
+    None
+  }
+}
+
+///
+/// Sealed trait
+///
+impl Sealed for Pool {}
+
+///
+/// IsInitialized trait
+///
+impl IsInitialized for Pool {
+  fn is_initialized(&self) -> bool {
+    self.state != PoolState::Uninitialized
+  }
+}
+
+///
+/// Pack trait
+///
+impl Pack for Pool {
+  // Fixed length
+  const LEN: usize = Pool::LEGACY_LEN + 1 + 1 + 8 + 8 + 8 + 8 + 3 * 8 + 32;
+  // Unpack data from [u8] to the data struct
+  fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+    msg!("Read pool data");
+    let src = array_ref![src, 0, Pool::LEN];
+    let (
+      legacy,
+      version,
+      accrue_earning,
+      pending_earning,
+      max_lpt_supply,
+      swap_count,
+      last_swap_slot,
+      min_reserve_s,
+      min_reserve_a,
+      min_reserve_b,
+      treasury_authority,
+    ) = array_refs![src, Pool::LEGACY_LEN, 1, 1, 8, 8, 8, 8, 8, 8, 8, 32];
+    let mut pool = Self::unpack_legacy(legacy)?;
+    // A fresh account is still zeroed, anything else must carry the current version
+    if version[0] != POOL_VERSION && (version[0] != 0 || pool.is_initialized()) {
+      return Err(ProgramError::InvalidAccountData);
+    }
+    pool.accrue_earning = match accrue_earning {
+      [0] => false,
+      [1] => true,
+      _ => return Err(ProgramError::InvalidAccountData),
+    };
+    pool.pending_earning = u64::from_le_bytes(*pending_earning);
+    pool.max_lpt_supply = u64::from_le_bytes(*max_lpt_supply);
+    pool.swap_count = u64::from_le_bytes(*swap_count);
+    pool.last_swap_slot = u64::from_le_bytes(*last_swap_slot);
+    pool.min_reserve_s = u64::from_le_bytes(*min_reserve_s);
+    pool.min_reserve_a = u64::from_le_bytes(*min_reserve_a);
+    pool.min_reserve_b = u64::from_le_bytes(*min_reserve_b);
+    pool.treasury_authority = Pubkey::new_from_array(*treasury_authority);
+    Ok(pool)
+  }
+  // Pack data from the data struct to [u8]
+  fn pack_into_slice(&self, dst: &mut [u8]) {
+    msg!("Write pool data");
+    let dst = array_mut_ref![dst, 0, Pool::LEN];
+    let (
+      dst_legacy,
+      dst_version,
+      dst_accrue_earning,
+      dst_pending_earning,
+      dst_max_lpt_supply,
+      dst_swap_count,
+      dst_last_swap_slot,
+      dst_min_reserve_s,
+      dst_min_reserve_a,
+      dst_min_reserve_b,
+      dst_treasury_authority,
+    ) = mut_array_refs![dst, Pool::LEGACY_LEN, 1, 1, 8, 8, 8, 8, 8, 8, 8, 32];
+    self.pack_legacy(dst_legacy);
+    *dst_version = [POOL_VERSION];
+    *dst_accrue_earning = [self.accrue_earning as u8];
+    *dst_pending_earning = self.pending_earning.to_le_bytes();
+    *dst_max_lpt_supply = self.max_lpt_supply.to_le_bytes();
+    *dst_swap_count = self.swap_count.to_le_bytes();
+    *dst_last_swap_slot = self.last_swap_slot.to_le_bytes();
+    *dst_min_reserve_s = self.min_reserve_s.to_le_bytes();
+    *dst_min_reserve_a = self.min_reserve_a.to_le_bytes();
+    *dst_min_reserve_b = self.min_reserve_b.to_le_bytes();
+    dst_treasury_authority.copy_from_slice(self.treasury_authority.as_ref());
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::schema::golden::decode;
+
+  // A version 1 pool account as stored on chain
+  const V1_POOL: &str = concat!(
+    "0101010101010101010101010101010101010101010101010101010101010101", // owner
+    "01",                                                               // state
+    "0202020202020202020202020202020202020202020202020202020202020202", // mint_lpt
+    "0303030303030303030303030303030303030303030303030303030303030303", // vault
+    "0404040404040404040404040404040404040404040404040404040404040404", // mint_s
+    "0505050505050505050505050505050505050505050505050505050505050505", // treasury_s
+    "40420f0000000000",                                                 // reserve_s
+    "0606060606060606060606060606060606060606060606060606060606060606", // mint_a
+    "0707070707070707070707070707070707070707070707070707070707070707", // treasury_a
+    "80841e0000000000",                                                 // reserve_a
+    "0808080808080808080808080808080808080808080808080808080808080808", // mint_b
+    "0909090909090909090909090909090909090909090909090909090909090909", // treasury_b
+    "c0c62d0000000000",                                                 // reserve_b
+  );
+
+  // A fully populated pool in the current layout
+  const V2_POOL: &str = concat!(
+    "0101010101010101010101010101010101010101010101010101010101010101", // owner
+    "02",                                                               // state
+    "0202020202020202020202020202020202020202020202020202020202020202", // mint_lpt
+    "0303030303030303030303030303030303030303030303030303030303030303", // vault
+    "0404040404040404040404040404040404040404040404040404040404040404", // mint_s
+    "0505050505050505050505050505050505050505050505050505050505050505", // treasury_s
+    "40420f0000000000",                                                 // reserve_s
+    "0606060606060606060606060606060606060606060606060606060606060606", // mint_a
+    "0707070707070707070707070707070707070707070707070707070707070707", // treasury_a
+    "80841e0000000000",                                                 // reserve_a
+    "0808080808080808080808080808080808080808080808080808080808080808", // mint_b
+    "0909090909090909090909090909090909090909090909090909090909090909", // treasury_b
+    "c0c62d0000000000",                                                 // reserve_b
+    "02",                                                               // version
+    "01",                                                               // accrue_earning
+    "00093d0000000000",                                                 // pending_earning
+    "404b4c0000000000",                                                 // max_lpt_supply
+    "0600000000000000",                                                 // swap_count
+    "c0cf6a0000000000",                                                 // last_swap_slot
+    "00127a0000000000",                                                 // min_reserve_s
+    "4054890000000000",                                                 // min_reserve_a
+    "8096980000000000",                                                 // min_reserve_b
+    "0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a", // treasury_authority
+  );
+
+  #[test]
+  fn from_legacy_reads_a_v1_account() {
+    let v1 = decode(V1_POOL);
+    assert_eq!(v1.len(), Pool::LEGACY_LEN);
+    let pool = Pool::from_legacy(&v1).unwrap();
+    assert_eq!(pool.owner, Pubkey::new_from_array([1; 32]));
+    assert_eq!(pool.state, PoolState::Initialized);
+    assert_eq!(pool.vault, Pubkey::new_from_array([3; 32]));
+    assert_eq!(pool.treasury_s, Pubkey::new_from_array([5; 32]));
+    assert_eq!(pool.reserve_s, 1_000_000);
+    assert_eq!(pool.treasury_a, Pubkey::new_from_array([7; 32]));
+    assert_eq!(pool.reserve_a, 2_000_000);
+    assert_eq!(pool.treasury_b, Pubkey::new_from_array([9; 32]));
+    assert_eq!(pool.reserve_b, 3_000_000);
+    // Fields added after version 1 take their defaults
+    assert!(!pool.accrue_earning);
+    assert_eq!(pool.pending_earning, 0);
+    assert_eq!(pool.max_lpt_supply, 0);
+    assert_eq!(
+      (pool.min_reserve_s, pool.min_reserve_a, pool.min_reserve_b),
+      (0, 0, 0)
+    );
+    assert_eq!(pool.treasury_authority, Pubkey::default());
+  }
+
+  #[test]
+  fn from_legacy_rejects_other_lengths() {
+    let v1 = decode(V1_POOL);
+    assert!(Pool::from_legacy(&v1[..Pool::LEGACY_LEN - 1]).is_err());
+    assert!(Pool::from_legacy(&[0; Pool::LEN]).is_err());
+  }
+
+  #[test]
+  fn v1_round_trips_through_v2() {
+    let v1 = decode(V1_POOL);
+    let pool = Pool::from_legacy(&v1).unwrap();
+    let mut v2 = vec![0; Pool::LEN];
+    Pool::pack(pool, &mut v2).unwrap();
+    // The v1 fields keep their offsets, the version follows them
+    assert_eq!(&v2[..Pool::LEGACY_LEN], &v1[..]);
+    assert_eq!(v2[Pool::LEGACY_LEN], POOL_VERSION);
+    assert_eq!(Pool::unpack(&v2).unwrap(), pool);
+  }
+
+  #[test]
+  fn unpack_versioned_reads_both_layouts() {
+    let v1 = decode(V1_POOL);
+    let v2 = decode(V2_POOL);
+    assert_eq!(
+      Pool::unpack_versioned(&v1).unwrap(),
+      Pool::from_legacy(&v1).unwrap()
+    );
+    assert_eq!(
+      Pool::unpack_versioned(&v2).unwrap(),
+      Pool::unpack(&v2).unwrap()
+    );
+    assert_eq!(
+      Pool::unpack_versioned(&[0; Pool::LEGACY_LEN]),
+      Err(ProgramError::UninitializedAccount)
+    );
+    assert_eq!(
+      Pool::unpack_versioned(&v1[..Pool::LEGACY_LEN - 1]),
+      Err(ProgramError::InvalidAccountData)
+    );
+  }
+
+  #[test]
+  fn pack_versioned_keeps_a_v1_account_in_v1() {
+    let mut v1 = decode(V1_POOL);
+    let mut pool = Pool::unpack_versioned(&v1).unwrap();
+    pool.reserve_a += 1;
+    // Counters have nowhere to go and are dropped
+    pool.swap_count = 1;
+    pool.last_swap_slot = 2;
+    Pool::pack_versioned(pool, &mut v1).unwrap();
+    let read = Pool::unpack_versioned(&v1).unwrap();
+    assert_eq!(read.reserve_a, 2_000_001);
+    assert_eq!((read.swap_count, read.last_swap_slot), (0, 0));
+    // A v2 account keeps every field
+    let mut v2 = decode(V2_POOL);
+    Pool::pack_versioned(pool, &mut v2).unwrap();
+    assert_eq!(Pool::unpack_versioned(&v2).unwrap(), pool);
+  }
+
+  #[test]
+  fn pack_versioned_refuses_settings_a_v1_account_cannot_hold() {
+    let mut v1 = decode(V1_POOL);
+    let pool = Pool::unpack_versioned(&v1).unwrap();
+    let settings = [
+      Pool {
+        accrue_earning: true,
+        ..pool
+      },
+      Pool {
+        pending_earning: 1,
+        ..pool
+      },
+      Pool {
+        max_lpt_supply: 1,
+        ..pool
+      },
+      Pool {
+        min_reserve_s: 1,
+        ..pool
+      },
+      Pool {
+        min_reserve_a: 1,
+        ..pool
+      },
+      Pool {
+        min_reserve_b: 1,
+        ..pool
+      },
+      Pool {
+        treasury_authority: Pubkey::new_from_array([10; 32]),
+        ..pool
+      },
+    ];
+    for setting in settings.iter() {
+      assert_eq!(
+        Pool::pack_versioned(*setting, &mut v1),
+        Err(ProgramError::AccountDataTooSmall)
+      );
+    }
+    assert_eq!(v1, decode(V1_POOL));
+  }
+
+  #[test]
+  fn unpack_requires_the_current_version() {
+    let pool = Pool::from_legacy(&decode(V1_POOL)).unwrap();
+    let mut v2 = vec![0; Pool::LEN];
+    Pool::pack(pool, &mut v2).unwrap();
+    for version in [0, 1, POOL_VERSION + 1].iter() {
+      v2[Pool::LEGACY_LEN] = *version;
+      assert_eq!(Pool::unpack(&v2), Err(ProgramError::InvalidAccountData));
+    }
+    // A fresh account has no version yet
+    let fresh = Pool::unpack_unchecked(&[0; Pool::LEN]).unwrap();
+    assert_eq!(fresh, Pool::default());
+  }
+
+  #[test]
+  fn layout_matches_the_golden_fixture() {
+    let key = |byte| Pubkey::new_from_array([byte; 32]);
+    let pool = Pool {
+      owner: key(1),
+      state: PoolState::Frozen,
+      mint_lpt: key(2),
+      vault: key(3),
+      mint_s: key(4),
+      treasury_s: key(5),
+      reserve_s: 1_000_000,
+      mint_a: key(6),
+      treasury_a: key(7),
+      reserve_a: 2_000_000,
+      mint_b: key(8),
+      treasury_b: key(9),
+      reserve_b: 3_000_000,
+      accrue_earning: true,
+      pending_earning: 4_000_000,
+      max_lpt_supply: 5_000_000,
+      swap_count: 6,
+      last_swap_slot: 7_000_000,
+      min_reserve_s: 8_000_000,
+      min_reserve_a: 9_000_000,
+      min_reserve_b: 10_000_000,
+      treasury_authority: key(10),
+    };
+    // Any layout change must come with an intentional update of the fixture
+    let golden = decode(V2_POOL);
+    let mut packed = vec![0; Pool::LEN];
+    Pool::pack(pool, &mut packed).unwrap();
+    assert_eq!(packed, golden);
+    assert_eq!(Pool::unpack(&golden).unwrap(), pool);
+  }
 }